@@ -1,9 +1,18 @@
-use std::{fs::File, io::Error as IOError};
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Error as IOError, Write},
+    path::{Path, PathBuf},
+    process,
+};
 
+use clap::{ArgEnum, Parser};
 use im_already_raytracer::{
+    filter::Filter,
+    image::ToneMapping,
     presets,
-    render::{render, RenderOptions},
-    Error as IARTError,
+    render::{render_with_sample_map, Integrator, RenderOptions},
+    DisplayTransform, Error as IARTError, Image, Scene,
 };
 use png::EncodingError;
 
@@ -12,6 +21,9 @@ pub enum Error {
     RayTracerError(IARTError),
     EncodingError(EncodingError),
     IOError(IOError),
+    UnknownFormat(PathBuf),
+    /// An option from a scene file that can't be rendered with
+    InvalidOption(&'static str),
 }
 
 impl From<IARTError> for Error {
@@ -32,25 +44,267 @@ impl From<IOError> for Error {
     }
 }
 
-fn main() -> Result<(), Error> {
-    let (camera, shapes, lights) = presets::cornellbox();
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RayTracerError(err) => write!(f, "{}", err),
+            Self::EncodingError(err) => write!(f, "{}", err),
+            Self::IOError(err) => write!(f, "{}", err),
+            Self::UnknownFormat(path) => write!(
+                f,
+                "can't tell the image format of `{}`, use .png, .exr, .hdr or .pfm",
+                path.display()
+            ),
+            Self::InvalidOption(name) => write!(f, "`{}` must be at least 1", name),
+        }
+    }
+}
+
+/// Renders a scene to a PNG, OpenEXR, Radiance HDR or PFM image
+#[derive(Parser)]
+#[clap(about)]
+struct Args {
+    /// The preset scene to render
+    #[clap(long, arg_enum, conflicts_with = "scene")]
+    preset: Option<Preset>,
+    /// A scene file to render instead of a preset
+    #[clap(long)]
+    scene: Option<PathBuf>,
+    /// Where to write the image. The format is picked from the extension.
+    #[clap(short, long, default_value = "output.png")]
+    output: PathBuf,
+    /// Image width in pixels
+    #[clap(long, parse(try_from_str = parse_positive))]
+    width: Option<usize>,
+    /// Image height in pixels
+    #[clap(long, parse(try_from_str = parse_positive))]
+    height: Option<usize>,
+    /// Rays per pixel
+    #[clap(long, parse(try_from_str = parse_positive))]
+    pixel_samples: Option<usize>,
+    /// How the rays in and around each pixel are weighted
+    #[clap(long, arg_enum)]
+    filter: Option<FilterArg>,
+    /// Keep sampling pixels whose relative noise is above this
+    #[clap(long, parse(try_from_str = parse_positive_f32))]
+    noise_threshold: Option<f32>,
+    /// The most rays a pixel gets with adaptive sampling
+    #[clap(long, parse(try_from_str = parse_positive))]
+    max_pixel_samples: Option<usize>,
+    /// Also write an image of how many rays each pixel got
+    #[clap(long)]
+    sample_map: Option<PathBuf>,
+    /// Shadow rays per light, which make shadows soft
+    #[clap(long, parse(try_from_str = parse_positive))]
+    shadow_samples: Option<usize>,
+    /// How many times a ray may be reflected or refracted
+    #[clap(long, parse(try_from_str = parse_positive))]
+    max_ray_depth: Option<usize>,
+    /// Seed for the randomness, making renders reproducible
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Use path tracing, with light bouncing between surfaces
+    #[clap(long)]
+    path_tracing: bool,
+    /// Don't clamp colors to 0..1, to keep highlights in HDR images. Colors
+    /// are never clamped when tone mapping
+    #[clap(long)]
+    no_clamp: bool,
+    /// Brightness adjustment in stops for PNG images
+    #[clap(long, allow_hyphen_values = true)]
+    exposure: Option<f32>,
+    /// How colors brighter than white are handled in PNG images
+    #[clap(long, arg_enum)]
+    tone_mapping: Option<ToneMappingArg>,
+    /// Write linear colors to PNG images instead of sRGB
+    #[clap(long)]
+    linear: bool,
+    /// Number of threads to render with
+    #[clap(long, parse(try_from_str = parse_positive))]
+    threads: Option<usize>,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum Preset {
+    Cornellbox,
+    StickInWater,
+    LightAndBox,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum FilterArg {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum ToneMappingArg {
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Png,
+    Exr,
+    Hdr,
+    Pfm,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "exr" => Some(Self::Exr),
+            "hdr" => Some(Self::Hdr),
+            "pfm" => Some(Self::Pfm),
+            _ => None,
+        }
+    }
+}
+
+fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("`{}` isn't a positive integer", s)),
+    }
+}
+
+fn parse_positive_f32(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(x) if x.is_finite() && x > 0. => Ok(x),
+        Ok(_) => Err("must be a finite number above 0".to_string()),
+        Err(_) => Err(format!("`{}` isn't a number", s)),
+    }
+}
+
+fn main() {
+    if let Err(err) = run(Args::parse()) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
 
-    let options = RenderOptions {
-        width: 512,
-        height: 512,
-        multisampling: 2,
-        soft_shadow_resolution: 2,
-        ..Default::default()
+fn run(args: Args) -> Result<(), Error> {
+    // Checked before rendering so that a typo doesn't waste a long render
+    let format =
+        Format::from_path(&args.output).ok_or_else(|| Error::UnknownFormat(args.output.clone()))?;
+    let sample_map = match &args.sample_map {
+        Some(path) => Some((
+            path,
+            Format::from_path(path).ok_or_else(|| Error::UnknownFormat(path.clone()))?,
+        )),
+        None => None,
     };
-    let image = render(&options, &camera, &shapes, &lights);
 
-    let file = File::create("output.png").unwrap();
-    let mut encoder = png::Encoder::new(file, options.width as u32, options.height as u32);
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    encoder
-        .write_header()?
-        .write_image_data(&image.get_raw_data())?;
+    let (scene, mut options) = match &args.scene {
+        Some(path) => Scene::load(path)?,
+        None => {
+            let scene = match args.preset.unwrap_or(Preset::Cornellbox) {
+                Preset::Cornellbox => presets::cornellbox(),
+                Preset::StickInWater => presets::stick_in_water(),
+                Preset::LightAndBox => presets::light_and_box(),
+            };
+            let options = RenderOptions {
+                width: 512,
+                height: 512,
+                pixel_samples: 4,
+                shadow_samples: 9,
+                ..Default::default()
+            };
+            (scene, options)
+        }
+    };
+
+    options.width = args.width.unwrap_or(options.width);
+    options.height = args.height.unwrap_or(options.height);
+    options.pixel_samples = args.pixel_samples.unwrap_or(options.pixel_samples);
+    if args.noise_threshold.is_some() || args.max_pixel_samples.is_some() {
+        let adaptive = options.adaptive.get_or_insert_with(Default::default);
+        adaptive.threshold = args.noise_threshold.unwrap_or(adaptive.threshold);
+        adaptive.max_samples = args.max_pixel_samples.unwrap_or(adaptive.max_samples);
+    }
+    if let Some(filter) = args.filter {
+        options.filter = match filter {
+            FilterArg::Box => Filter::Box,
+            FilterArg::Tent => Filter::Tent,
+            FilterArg::Gaussian => Filter::Gaussian,
+            FilterArg::Mitchell => Filter::Mitchell,
+        };
+    }
+    options.shadow_samples = args.shadow_samples.unwrap_or(options.shadow_samples);
+    options.max_ray_depth = args.max_ray_depth.unwrap_or(options.max_ray_depth);
+    options.threads = args.threads.unwrap_or(options.threads);
+    options.seed = args.seed.or(options.seed);
+    if args.no_clamp {
+        options.clamp_colors = false;
+    }
+    options.display.exposure = args.exposure.unwrap_or(options.display.exposure);
+    if let Some(tone_mapping) = args.tone_mapping {
+        options.display.tone_mapping = match tone_mapping {
+            ToneMappingArg::Clamp => ToneMapping::Clamp,
+            ToneMappingArg::Reinhard => ToneMapping::Reinhard,
+            ToneMappingArg::Aces => ToneMapping::AcesFilmic,
+        };
+    }
+    if args.linear {
+        options.display.srgb = false;
+    }
+    if args.path_tracing {
+        options.integrator = Integrator::PathTracing;
+    }
+    // The arguments are already checked, but scene files can set anything
+    for (name, value) in [
+        ("width", options.width),
+        ("height", options.height),
+        ("pixel_samples", options.pixel_samples),
+        ("shadow_samples", options.shadow_samples),
+    ] {
+        if value == 0 {
+            return Err(Error::InvalidOption(name));
+        }
+    }
+
+    let (image, map) = render_with_sample_map(&options, &scene);
+    write_image(&image, &args.output, format, &options.display)?;
+    if let Some((path, format)) = sample_map {
+        // The counts are data, so they aren't tone mapped or sRGB encoded
+        let display = DisplayTransform {
+            srgb: false,
+            ..DisplayTransform::new()
+        };
+        write_image(&map, path, format, &display)?;
+    }
 
     Ok(())
 }
+
+fn write_image(
+    image: &Image,
+    path: &Path,
+    format: Format,
+    display: &DisplayTransform,
+) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Png => {
+            let mut encoder =
+                png::Encoder::new(&mut writer, image.width as u32, image.height as u32);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .write_header()?
+                .write_image_data(&image.get_raw_data(display))?;
+        }
+        Format::Exr => image.write_exr(&mut writer)?,
+        Format::Hdr => image.write_hdr(&mut writer)?,
+        Format::Pfm => image.write_pfm(&mut writer)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::{
+    f32::consts::{PI, TAU},
+    path::Path,
+    sync::Arc,
+};
+
+use vek::Lerp;
+
+use crate::{Error, Image, Vec3};
+
+/// What rays that don't hit anything see
+#[derive(Clone)]
+pub enum Background {
+    Solid(Vec3),
+    /// Blends from `bottom` straight down to `top` straight up
+    Gradient {
+        bottom: Vec3,
+        top: Vec3,
+    },
+    EnvironmentMap(EnvironmentMap),
+}
+
+impl Background {
+    /// The color seen in the direction, which must be normalized
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Self::Solid(color) => *color,
+            Self::Gradient { bottom, top } => Lerp::lerp(*bottom, *top, (direction.y + 1.) / 2.),
+            Self::EnvironmentMap(map) => map.color(direction),
+        }
+    }
+
+    /// Whether the background is black in every direction, so that it doesn't
+    /// light anything
+    pub fn is_black(&self) -> bool {
+        match self {
+            Self::Solid(color) => *color == Vec3::zero(),
+            Self::Gradient { bottom, top } => *bottom == Vec3::zero() && *top == Vec3::zero(),
+            Self::EnvironmentMap(map) => map.strength == 0.,
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::Solid(Vec3::zero())
+    }
+}
+
+/// An equirectangular image of everything around the scene, with the middle
+/// of the image in the -z direction and the top straight up
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    image: Arc<Image>,
+    /// What the colors of the image are multiplied by
+    pub strength: f32,
+    /// Rotation around the y axis in radians
+    pub rotation: f32,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: impl Into<Arc<Image>>) -> Self {
+        Self {
+            image: image.into(),
+            strength: 1.,
+            rotation: 0.,
+        }
+    }
+
+    /// Loads the image from a `.hdr` or `.pfm` file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self::new(Image::load(path)?))
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        let Image {
+            data,
+            width,
+            height,
+        } = &*self.image;
+        if data.is_empty() {
+            return Vec3::zero();
+        }
+        let u = (direction.x.atan2(-direction.z) + self.rotation) / TAU + 0.5;
+        let v = direction.y.clamp(-1., 1.).acos() / PI;
+        let x = (u.rem_euclid(1.) * *width as f32) as usize % width;
+        let y = ((v * *height as f32) as usize).min(height - 1);
+        data[y * width + x] * self.strength
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{
+    shapes::{Intersect, Intersection},
+    Ray, Vec3,
+};
+
+/// Maximum number of items in a leaf node
+const LEAF_SIZE: usize = 4;
+
+/// An axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// A box containing nothing, the identity for `union`
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::broadcast(f32::INFINITY),
+            max: Vec3::broadcast(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points
+            .into_iter()
+            .fold(Self::empty(), |aabb, p| aabb.union(Self::new(p, p)))
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: Vec3::partial_min(self.min, other.min),
+            max: Vec3::partial_max(self.max, other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.
+    }
+
+    /// Returns the distance along the ray to where it enters the box, if it
+    /// does so before `max_dist`. `inv_direction` is the component-wise
+    /// reciprocal of the ray direction.
+    fn entry(&self, ray: Ray, inv_direction: Vec3, max_dist: f32) -> Option<f32> {
+        let t0 = (self.min - ray.origin) * inv_direction;
+        let t1 = (self.max - ray.origin) * inv_direction;
+        let near = Vec3::partial_min(t0, t1).reduce_partial_max().max(0.);
+        let far = Vec3::partial_max(t0, t1).reduce_partial_min().min(max_dist);
+        if near <= far {
+            Some(near)
+        } else {
+            None
+        }
+    }
+}
+
+pub trait Bounded {
+    /// The bounding box of `self`, or `None` if it is unbounded (e.g. an
+    /// infinite plane)
+    fn bounds(&self) -> Option<Aabb>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        end: usize,
+    },
+    Branch {
+        bounds: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> Aabb {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Branch { bounds, .. } => *bounds,
+        }
+    }
+}
+
+/// A bounding volume hierarchy over a slice of items. It only stores indices,
+/// so it can be built once and reused as long as the items don't move.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Item indices, ordered so that every leaf refers to a contiguous range
+    indices: Vec<usize>,
+    /// Items without bounds, which are tested against every ray
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new<T: Bounded>(items: &[T]) -> Self {
+        let mut bvh = Self::default();
+        let mut bounded = vec![];
+        for (i, item) in items.iter().enumerate() {
+            match item.bounds() {
+                Some(bounds) => bounded.push((i, bounds)),
+                None => bvh.unbounded.push(i),
+            }
+        }
+        if !bounded.is_empty() {
+            bvh.build(&mut bounded);
+        }
+        bvh
+    }
+
+    /// Builds the subtree containing `items` and returns the index of its root
+    fn build(&mut self, items: &mut [(usize, Aabb)]) -> usize {
+        let start = self.indices.len();
+        let bounds = items
+            .iter()
+            .fold(Aabb::empty(), |acc, (_, b)| acc.union(*b));
+
+        if items.len() <= LEAF_SIZE {
+            self.indices.extend(items.iter().map(|(i, _)| i));
+            self.nodes.push(Node::Leaf {
+                bounds,
+                start,
+                end: start + items.len(),
+            });
+            return self.nodes.len() - 1;
+        }
+
+        // Split at the median along the axis where the centers are most spread out
+        let centers = Aabb::from_points(items.iter().map(|(_, b)| b.center()));
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        items.sort_unstable_by(|(_, a), (_, b)| {
+            a.center()[axis]
+                .partial_cmp(&b.center()[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let (left_items, right_items) = items.split_at_mut(items.len() / 2);
+
+        let node = self.nodes.len();
+        // Placeholder until the children are built
+        self.nodes.push(Node::Leaf {
+            bounds,
+            start,
+            end: start,
+        });
+        let left = self.build(left_items);
+        let right = self.build(right_items);
+        self.nodes[node] = Node::Branch {
+            bounds,
+            left,
+            right,
+        };
+        node
+    }
+
+    fn entry(&self, node: usize, ray: Ray, inv_direction: Vec3, max_dist: f32) -> Option<f32> {
+        self.nodes[node]
+            .bounds()
+            .entry(ray, inv_direction, max_dist)
+    }
+
+    /// Finds the closest intersection among the items. `intersect` is called
+    /// with the index of every item whose bounding box the ray passes through.
+    pub fn closest(
+        &self,
+        ray: Ray,
+        mut intersect: impl FnMut(usize) -> Option<Intersection>,
+    ) -> Option<(usize, Intersection)> {
+        let mut min_dist = f32::MAX;
+        let mut closest = None;
+        let mut test = |i: usize, min_dist: &mut f32| match intersect(i) {
+            Some(intersection) if intersection.dist < *min_dist => {
+                *min_dist = intersection.dist;
+                closest = Some((i, intersection));
+            }
+            _ => {}
+        };
+
+        for &i in &self.unbounded {
+            test(i, &mut min_dist);
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let inv_direction = Vec3::one() / ray.direction;
+        if self.entry(0, ray, inv_direction, min_dist).is_none() {
+            return closest;
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            match self.nodes[node] {
+                Node::Leaf { start, end, .. } => {
+                    for &i in &self.indices[start..end] {
+                        test(i, &mut min_dist);
+                    }
+                }
+                Node::Branch { left, right, .. } => {
+                    let left_entry = self.entry(left, ray, inv_direction, min_dist);
+                    let right_entry = self.entry(right, ray, inv_direction, min_dist);
+                    // Push the nearest child last so that it is visited first
+                    match (left_entry, right_entry) {
+                        (Some(l), Some(r)) if l < r => stack.extend([right, left]),
+                        (Some(_), Some(_)) => stack.extend([left, right]),
+                        (Some(_), None) => stack.push(left),
+                        (None, Some(_)) => stack.push(right),
+                        (None, None) => {}
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+
+    /// The bounds of all the items, or `None` if any of them are unbounded
+    pub fn bounds(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        Some(self.nodes.first().map_or(Aabb::empty(), Node::bounds))
+    }
+
+    /// Finds the closest intersection between the ray and `items`, which must
+    /// be the same items as the hierarchy was built from
+    pub fn intersection<'s, T: Intersect>(
+        &self,
+        ray: Ray,
+        items: &'s [T],
+        ignore_normal: Option<Vec3>,
+    ) -> Option<(&'s T, Intersection)> {
+        self.closest(ray, |i| items[i].intersection(ray, ignore_normal))
+            .map(|(i, intersection)| (&items[i], intersection))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::shapes::Sphere;
+
+    #[test]
+    fn test_same_as_linear() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut random_vec = |scale: f32| {
+            Vec3::new(
+                rng.gen_range(-scale..scale),
+                rng.gen_range(-scale..scale),
+                rng.gen_range(-scale..scale),
+            )
+        };
+        let spheres: Vec<_> = (0..500)
+            .map(|i| Sphere {
+                center: random_vec(20.),
+                radius: 0.1 + (i % 7) as f32 * 0.2,
+            })
+            .collect();
+        let bvh = Bvh::new(&spheres);
+
+        for _ in 0..1000 {
+            let ray = Ray::new(random_vec(25.), random_vec(1.).normalized());
+            let linear = spheres
+                .iter()
+                .filter_map(|s| s.intersection(ray, None))
+                .map(|i| i.dist)
+                .fold(f32::MAX, f32::min);
+            let accelerated = bvh
+                .intersection(ray, &spheres, None)
+                .map_or(f32::MAX, |(_, i)| i.dist);
+            assert_eq!(linear, accelerated, "ray={:?}", ray);
+        }
+    }
+}
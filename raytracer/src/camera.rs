@@ -1,12 +1,35 @@
-use crate::{Quaternion, Ray, Vec3};
+use std::f32::consts::{FRAC_PI_2, TAU};
 
+use serde::{Deserialize, Serialize};
+
+use crate::{concentric_disk, Quaternion, Ray, Vec2, Vec3};
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Camera {
     pub position: Vec3,
     pub orientation: Quaternion,
     pub fov: f32,
     pub mapping_function: MappingFunction,
+    /// The radius of the lens. Rays start all over it, which blurs everything
+    /// that isn't `focus_distance` away. 0 makes a pinhole camera, where
+    /// everything is sharp.
+    #[serde(default)]
+    pub aperture: f32,
+    /// How far in front of the camera things are sharp, which is on a plane
+    /// facing the camera
+    #[serde(default = "default_focus_distance")]
+    pub focus_distance: f32,
+    /// The number of blades in the aperture, which makes blurred highlights
+    /// polygons with that many corners instead of circles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aperture_blades: Option<usize>,
 }
 
+fn default_focus_distance() -> f32 {
+    1.
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum MappingFunction {
     Linear,
     Unlinear,
@@ -60,19 +83,118 @@ pub struct Rays<'c> {
     height: usize,
 }
 
+/// A point on the unit disk, or on a regular polygon with `blades` corners on
+/// the unit circle, spread evenly over it as `uv` covers 0..1 × 0..1
+fn aperture_point(blades: Option<usize>, uv: Vec2) -> Vec2 {
+    match blades {
+        Some(blades) if blades >= 3 => {
+            // A triangle between the middle and one side, with a corner at
+            // the top
+            let blades = blades as f32;
+            let side = (uv.x * blades).floor().min(blades - 1.);
+            let corner = |i: f32| {
+                let angle = FRAC_PI_2 + i * TAU / blades;
+                Vec2::new(angle.cos(), angle.sin())
+            };
+            let edge = Vec2::lerp(corner(side), corner(side + 1.), uv.x * blades - side);
+            edge * uv.y.sqrt()
+        }
+        _ => concentric_disk(uv),
+    }
+}
+
 impl<'c> Rays<'c> {
-    pub fn get(&self, x: usize, y: usize) -> Ray {
+    /// The ray through a point on the image in pixels, where whole numbers
+    /// are the middles of pixels, from the point on the lens that `lens` in
+    /// 0..1 × 0..1 maps to
+    pub fn get(&self, pixel: Vec2, lens: Vec2) -> Ray {
         let v_fov = self.camera.fov;
         let aspect_ratio = self.width as f32 / self.height as f32;
 
-        let x01 = x as f32 / (self.width - 1) as f32;
-        let y01 = 1. - (y as f32 / (self.height - 1) as f32);
+        let x01 = (pixel.x + 0.5) / self.width as f32;
+        let y01 = 1. - (pixel.y + 0.5) / self.height as f32;
 
         let direction = self
             .camera
             .mapping_function
             .get_direction(x01, y01, v_fov, aspect_ratio);
 
-        Ray::new(self.camera.position, self.camera.orientation * direction)
+        let camera = self.camera;
+        if camera.aperture <= 0. {
+            return Ray::new(camera.position, camera.orientation * direction);
+        }
+        // Rays from all over the lens meet again on the plane at the focus
+        // distance, which sideways rays of circular images never reach
+        let focus = direction * (camera.focus_distance / (-direction.z).max(f32::EPSILON));
+        let origin = Vec3::from(aperture_point(camera.aperture_blades, lens) * camera.aperture);
+        Ray::new(
+            camera.position + camera.orientation * origin,
+            camera.orientation * (focus - origin).normalized(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_centers() {
+        let camera = Camera {
+            position: Vec3::zero(),
+            orientation: Quaternion::identity(),
+            fov: 1.,
+            mapping_function: MappingFunction::Linear,
+            aperture: 0.,
+            focus_distance: 1.,
+            aperture_blades: None,
+        };
+        // A single pixel looks straight ahead
+        let ray = camera.rays(1, 1).get(Vec2::zero(), Vec2::zero());
+        assert_eq!(ray.direction, -Vec3::unit_z());
+        // The edges of the image are the edges of the field of view
+        let corner = camera.rays(2, 2).get(Vec2::broadcast(-0.5), Vec2::zero());
+        let half_fov = (corner.direction.y / -corner.direction.z).atan();
+        assert!((half_fov - 0.5).abs() < 1e-6, "{}", half_fov);
+    }
+
+    #[test]
+    fn test_thin_lens() {
+        let camera = Camera {
+            position: Vec3::new(1., 2., 3.),
+            orientation: Quaternion::rotation_y(0.3),
+            fov: 1.,
+            mapping_function: MappingFunction::Linear,
+            aperture: 0.5,
+            focus_distance: 4.,
+            aperture_blades: Some(6),
+        };
+        let rays = camera.rays(10, 10);
+        let pixel = Vec2::new(2., 7.);
+        // Rays through the same pixel meet on the plane at the focus distance
+        let pinhole = Camera {
+            aperture: 0.,
+            ..camera.clone()
+        };
+        let middle = pinhole.rays(10, 10).get(pixel, Vec2::zero());
+        let forward = camera.orientation * -Vec3::unit_z();
+        let sharp = middle.origin + middle.direction * (4. / middle.direction.dot(forward));
+        for lens in [Vec2::new(0.1, 0.9), Vec2::new(0.7, 0.2)] {
+            let ray = rays.get(pixel, lens);
+            assert!((ray.origin - camera.position).magnitude() > 0.01);
+            let to_sharp = sharp - ray.origin;
+            let miss = to_sharp - ray.direction * to_sharp.dot(ray.direction);
+            assert!(miss.magnitude() < 1e-4, "{}", miss);
+        }
+
+        // The aperture is a hexagon, whose sides are cos(30°) from the middle
+        for i in 0..100 {
+            let uv = Vec2::new((i % 10) as f32 + 0.5, (i / 10) as f32 + 0.5) / 10.;
+            let point = aperture_point(Some(6), uv);
+            let angle = point.y.atan2(point.x) - FRAC_PI_2;
+            let side = (angle / (TAU / 6.)).floor() * TAU / 6. + TAU / 12.;
+            let dist = point.magnitude() * (angle - side).cos();
+            assert!(dist <= 30f32.to_radians().cos() + 1e-5);
+        }
     }
 }
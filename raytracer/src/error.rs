@@ -1,8 +1,47 @@
-use std::io::Error as IOError;
+use std::{fmt, io::Error as IOError, path::PathBuf};
 
 #[derive(Debug)]
 pub enum Error {
     IOError(IOError),
+    /// A file that couldn't be opened or read
+    FileError {
+        file: PathBuf,
+        err: IOError,
+    },
+    ParseError {
+        file: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// An image file that couldn't be read
+    ImageError {
+        file: PathBuf,
+        message: String,
+    },
+}
+
+impl Error {
+    pub fn file(file: impl Into<PathBuf>, err: IOError) -> Self {
+        Self::FileError {
+            file: file.into(),
+            err,
+        }
+    }
+
+    pub fn parse(file: impl Into<PathBuf>, line: usize, message: impl Into<String>) -> Self {
+        Self::ParseError {
+            file: file.into(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub fn image(file: impl Into<PathBuf>, message: impl Into<String>) -> Self {
+        Self::ImageError {
+            file: file.into(),
+            message: message.into(),
+        }
+    }
 }
 
 impl From<IOError> for Error {
@@ -10,3 +49,18 @@ impl From<IOError> for Error {
         Self::IOError(err)
     }
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IOError(err) => write!(f, "{}", err),
+            Self::FileError { file, err } => write!(f, "{}: {}", file.display(), err),
+            Self::ParseError {
+                file,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", file.display(), line, message),
+            Self::ImageError { file, message } => write!(f, "{}: {}", file.display(), message),
+        }
+    }
+}
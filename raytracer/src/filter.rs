@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+
+/// How the samples in and around a pixel are weighted to find its color. All
+/// of them are separable, the product of the same curve along both axes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Filter {
+    /// Every sample inside the pixel counts the same
+    Box,
+    /// Falls off linearly to one pixel from the middle
+    Tent,
+    /// A Gaussian with a standard deviation of half a pixel, cut off at 1.5
+    /// pixels
+    Gaussian,
+    /// The Mitchell–Netravali cubic with B = C = 1/3, which is sharper than
+    /// the Gaussian but rings slightly around edges. Its negative lobes make
+    /// it noisier with few samples.
+    Mitchell,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::Box
+    }
+}
+
+impl Filter {
+    /// How far from the middle of a pixel, in pixels, that samples affect it
+    pub fn radius(self) -> f32 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.,
+            Self::Gaussian => 1.5,
+            Self::Mitchell => 2.,
+        }
+    }
+
+    /// The unnormalized weight of a sample `x` pixels from the middle along
+    /// one axis
+    pub fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.;
+        }
+        match self {
+            Self::Box => 1.,
+            Self::Tent => 1. - x,
+            Self::Gaussian => {
+                // Shifted down so that it reaches 0 at the radius
+                let gaussian = |x: f32| (-2. * x * x).exp();
+                gaussian(x) - gaussian(self.radius())
+            }
+            Self::Mitchell => {
+                const B: f32 = 1. / 3.;
+                const C: f32 = 1. / 3.;
+                let (x2, x3) = (x * x, x * x * x);
+                if x < 1. {
+                    ((12. - 9. * B - 6. * C) * x3 + (-18. + 12. * B + 6. * C) * x2 + (6. - 2. * B))
+                        / 6.
+                } else {
+                    ((-B - 6. * C) * x3
+                        + (6. * B + 30. * C) * x2
+                        + (-12. * B - 48. * C) * x
+                        + (8. * B + 24. * C))
+                        / 6.
+                }
+            }
+        }
+    }
+}
+
+/// Bins that a filter is tabulated in for sampling
+const BINS: usize = 64;
+
+/// Turns evenly spread numbers into sample offsets distributed like the size
+/// of a filter, so that samples where it is large are more common rather than
+/// having larger weights. The order of the numbers is kept, so stratified
+/// numbers give stratified offsets.
+pub(crate) struct FilterSampler {
+    radius: f32,
+    /// The sum of the sizes of the bins before each one, normalized to end at 1
+    cdf: [f32; BINS + 1],
+    /// The weight of samples in each bin, which is their sign times how much
+    /// the sampled size differs from the actual filter in total
+    weights: [f32; BINS],
+}
+
+impl FilterSampler {
+    pub fn new(filter: Filter) -> Self {
+        let radius = filter.radius();
+        let values: Vec<_> = (0..BINS)
+            .map(|i| filter.weight(((i as f32 + 0.5) / BINS as f32 * 2. - 1.) * radius))
+            .collect();
+        let total: f32 = values.iter().map(|v| v.abs()).sum();
+        let signed: f32 = values.iter().sum();
+
+        let mut cdf = [0.; BINS + 1];
+        let mut weights = [0.; BINS];
+        for (i, value) in values.into_iter().enumerate() {
+            cdf[i + 1] = cdf[i] + value.abs() / total;
+            weights[i] = value.signum() * total / signed;
+        }
+        cdf[BINS] = 1.;
+        Self {
+            radius,
+            cdf,
+            weights,
+        }
+    }
+
+    /// The offset from the middle of the pixel for `u` in 0..1, along with the
+    /// weight of the sample. The weights average to 1.
+    pub fn sample(&self, u: f32) -> (f32, f32) {
+        let bin = (self.cdf.partition_point(|&c| c <= u) - 1).min(BINS - 1);
+        let within = (u - self.cdf[bin]) / (self.cdf[bin + 1] - self.cdf[bin]);
+        let x01 = (bin as f32 + within.clamp(0., 1.)) / BINS as f32;
+        (self.radius * (x01 * 2. - 1.), self.weights[bin])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_sampler() {
+        let samples = 10_000;
+        let us = (0..samples).map(|i| (i as f32 + 0.5) / samples as f32);
+
+        let tent = FilterSampler::new(Filter::Tent);
+        // The average distance from the middle is a third of a pixel
+        let mean_dist: f32 =
+            us.clone().map(|u| tent.sample(u).0.abs()).sum::<f32>() / samples as f32;
+        assert!((mean_dist - 1. / 3.).abs() < 1e-3, "{}", mean_dist);
+
+        for filter in [Filter::Box, Filter::Gaussian, Filter::Mitchell] {
+            let sampler = FilterSampler::new(filter);
+            assert!(sampler.sample(0.5).0.abs() < 1e-3);
+            let offsets: Vec<_> = us.clone().map(|u| sampler.sample(u)).collect();
+            assert!(offsets.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+            let mean_weight = offsets.iter().map(|(_, w)| w).sum::<f32>() / samples as f32;
+            assert!(
+                (mean_weight - 1.).abs() < 1e-3,
+                "{:?} {}",
+                filter,
+                mean_weight
+            );
+        }
+        // Mitchell's negative lobes are sampled with negative weights
+        let mitchell = FilterSampler::new(Filter::Mitchell);
+        assert!(mitchell.sample(0.001).1 < 0.);
+    }
+}
@@ -1,5 +1,90 @@
-use crate::Vec3;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
 
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Vec3};
+
+/// Turns the linear colors of a render into colors for a display
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayTransform {
+    /// Brightness adjustment in stops, where every stop doubles the brightness
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    /// Whether to encode the colors with the sRGB transfer function, as
+    /// displays expect, instead of writing the linear values
+    pub srgb: bool,
+}
+
+impl DisplayTransform {
+    pub const fn new() -> Self {
+        Self {
+            exposure: 0.,
+            tone_mapping: ToneMapping::Clamp,
+            srgb: true,
+        }
+    }
+
+    /// Maps a linear color to a display color in 0..=1
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let color = self.tone_mapping.apply(color * self.exposure.exp2());
+        let color = color.map(|c| c.clamp(0., 1.));
+        if self.srgb {
+            color.map(|c| {
+                if c <= 0.0031308 {
+                    c * 12.92
+                } else {
+                    1.055 * c.powf(1. / 2.4) - 0.055
+                }
+            })
+        } else {
+            color
+        }
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How colors brighter than 1 are brought into the displayable range
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ToneMapping {
+    /// Everything above 1 is cut off
+    Clamp,
+    /// `c / (1 + c)`, which never quite reaches white
+    Reinhard,
+    /// Reinhard, but reaching white at the given brightness
+    ReinhardExtended { white: f32 },
+    /// An approximation of the ACES filmic curve, with more contrast than
+    /// Reinhard
+    AcesFilmic,
+}
+
+impl ToneMapping {
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        let color = color.map(|c| c.max(0.));
+        match self {
+            Self::Clamp => color,
+            Self::Reinhard => color / (Vec3::one() + color),
+            Self::ReinhardExtended { white } => {
+                color * (Vec3::one() + color / (white * white)) / (Vec3::one() + color)
+            }
+            // By Krzysztof Narkowicz
+            Self::AcesFilmic => {
+                (color * (color * 2.51 + 0.03)) / (color * (color * 2.43 + 0.59) + 0.14)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Image {
     pub data: Vec<Vec3>,
     pub width: usize,
@@ -15,14 +100,476 @@ impl Image {
             height,
         }
     }
-    pub fn get_raw_data(&self) -> Vec<u8> {
+    /// The image as 8-bit RGBA for displaying or saving as e.g. PNG
+    pub fn get_raw_data(&self, transform: &DisplayTransform) -> Vec<u8> {
         let mut output = vec![0; self.width * self.height * 4];
         for (i, pixel) in self.data.iter().enumerate() {
-            output[i * 4 + 0] = (pixel.x * 255.).clamp(0., 255.) as u8;
-            output[i * 4 + 1] = (pixel.y * 255.).clamp(0., 255.) as u8;
-            output[i * 4 + 2] = (pixel.z * 255.).clamp(0., 255.) as u8;
+            let pixel = transform.apply(*pixel);
+            output[i * 4 + 0] = (pixel.x * 255.).round() as u8;
+            output[i * 4 + 1] = (pixel.y * 255.).round() as u8;
+            output[i * 4 + 2] = (pixel.z * 255.).round() as u8;
             output[i * 4 + 3] = 255;
         }
         output
     }
+
+    /// Loads a PNG, Radiance HDR (`.hdr`) or PFM image, depending on the
+    /// extension. PNG images are not converted from sRGB.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str());
+        let read = match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("png") => Self::read_png,
+            Some("hdr") => Self::read_hdr,
+            Some("pfm") => Self::read_pfm,
+            _ => {
+                return Err(Error::image(
+                    path,
+                    "only .png, .hdr and .pfm images can be loaded",
+                ))
+            }
+        };
+        let bytes = fs::read(path).map_err(|err| Error::file(path, err))?;
+        read(&bytes).map_err(|message| Error::image(path, message))
+    }
+
+    /// Reads a PNG image, scaling the colors to 0..=1
+    pub fn read_png(bytes: &[u8]) -> Result<Self, String> {
+        let mut decoder = png::Decoder::new(bytes);
+        // Palettes and bit depths other than 8 are converted to 8-bit colors
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        let channels = info.color_type.samples();
+        let data = buffer[..info.buffer_size()]
+            .chunks(channels)
+            .map(|pixel| {
+                let [r, g, b] = match *pixel {
+                    [gray] | [gray, _] => [gray; 3],
+                    [r, g, b, ..] => [r, g, b],
+                    _ => unreachable!(),
+                };
+                Vec3::new(r as f32, g as f32, b as f32) / 255.
+            })
+            .collect();
+        Ok(Self::new(data, info.width as usize, info.height as usize))
+    }
+
+    /// Reads a Radiance HDR image, with or without run length encoding
+    pub fn read_hdr(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader(bytes);
+        if !reader.line()?.starts_with("#?") {
+            return Err("not a Radiance HDR file".to_string());
+        }
+        // Variables such as the format come before an empty line
+        loop {
+            let line = reader.line()?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(format!("unsupported format `{}`", format));
+                }
+            }
+        }
+        let resolution = reader.line()?;
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (parse_size(height)?, parse_size(width)?),
+            _ => return Err(format!("unsupported orientation `{}`", resolution)),
+        };
+        // Run length encoded scanlines take at least two bytes per channel for
+        // every 127 pixels
+        let line_size = if (8..=0x7fff).contains(&width) {
+            Some(4 + 8 * (width / 127))
+        } else {
+            width.checked_mul(4)
+        };
+        reader.expect(line_size.and_then(|size| size.checked_mul(height)))?;
+
+        let mut data = Vec::with_capacity(width * height);
+        let mut scanline = vec![[0; 4]; width];
+        for _ in 0..height {
+            if (8..=0x7fff).contains(&width) && reader.peek(4)?[..2] == [2, 2] {
+                reader.take(4)?;
+                for channel in 0..4 {
+                    let mut x = 0;
+                    while x < width {
+                        let count = reader.take(1)?[0] as usize;
+                        let (len, bytes) = if count > 128 {
+                            (count - 128, reader.take(1)?.repeat(count - 128))
+                        } else {
+                            (count, reader.take(count)?.to_vec())
+                        };
+                        if len == 0 || x + len > width {
+                            return Err("invalid run length".to_string());
+                        }
+                        for (pixel, byte) in scanline[x..x + len].iter_mut().zip(bytes) {
+                            pixel[channel] = byte;
+                        }
+                        x += len;
+                    }
+                }
+            } else {
+                for pixel in &mut scanline {
+                    pixel.copy_from_slice(reader.take(4)?);
+                }
+            }
+            data.extend(scanline.iter().map(|&pixel| from_rgbe(pixel)));
+        }
+        Ok(Self::new(data, width, height))
+    }
+
+    /// Reads a color or grayscale Portable Float Map
+    pub fn read_pfm(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader(bytes);
+        let channels = match reader.word()? {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err("not a PFM file".to_string()),
+        };
+        let width = parse_size(reader.word()?)?;
+        let height = parse_size(reader.word()?)?;
+        let scale: f32 = reader
+            .word()?
+            .parse()
+            .map_err(|_| "invalid scale".to_string())?;
+        reader.expect(
+            width
+                .checked_mul(4 * channels)
+                .and_then(|size| size.checked_mul(height)),
+        )?;
+
+        let mut rows = Vec::with_capacity(height);
+        for _ in 0..height {
+            let mut row = Vec::with_capacity(width);
+            for _ in 0..width {
+                let mut pixel = [0.; 3];
+                for channel in &mut pixel[..channels] {
+                    let bytes = reader.take(4)?.try_into().unwrap();
+                    *channel = if scale < 0. {
+                        f32::from_le_bytes(bytes)
+                    } else {
+                        f32::from_be_bytes(bytes)
+                    };
+                }
+                if channels == 1 {
+                    pixel = [pixel[0]; 3];
+                }
+                row.push(Vec3::from(pixel));
+            }
+            rows.push(row);
+        }
+        // The rows are stored from the bottom up
+        Ok(Self::new(
+            rows.into_iter().rev().flatten().collect(),
+            width,
+            height,
+        ))
+    }
+
+    fn rows(&self) -> impl DoubleEndedIterator<Item = &[Vec3]> {
+        self.data.chunks(self.width.max(1))
+    }
+
+    /// Writes the image as a Portable Float Map, keeping the full range of
+    /// the colors
+    pub fn write_pfm(&self, mut writer: impl Write) -> io::Result<()> {
+        // A negative scale means little endian
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        // The rows are stored from the bottom up
+        for row in self.rows().rev() {
+            for pixel in row {
+                for channel in [pixel.x, pixel.y, pixel.z] {
+                    writer.write_all(&channel.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the image in the Radiance RGBE format (`.hdr`)
+    pub fn write_hdr(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+        for row in self.rows() {
+            let pixels: Vec<_> = row.iter().map(|&pixel| rgbe(pixel)).collect();
+            if !(8..=0x7fff).contains(&self.width) {
+                // Run length encoding only works for these widths
+                writer.write_all(&pixels.concat())?;
+                continue;
+            }
+            // Run length encoded scanlines store each channel separately. Only
+            // literal runs are used, which is valid but doesn't compress.
+            writer.write_all(&[2, 2, (self.width >> 8) as u8, self.width as u8])?;
+            for channel in 0..4 {
+                for run in pixels.chunks(128) {
+                    writer.write_all(&[run.len() as u8])?;
+                    let bytes: Vec<_> = run.iter().map(|pixel| pixel[channel]).collect();
+                    writer.write_all(&bytes)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the image as an uncompressed OpenEXR file with 32-bit float
+    /// channels
+    pub fn write_exr(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut header = vec![];
+        header.extend([0x76, 0x2f, 0x31, 0x01]);
+        // Version 2, single part scanline image
+        header.extend(2u32.to_le_bytes());
+
+        let mut channels = vec![];
+        // Channels have to be sorted by name
+        for name in ["B", "G", "R"] {
+            channels.extend(name.as_bytes());
+            channels.push(0);
+            // 32-bit float, not linear (ignored by readers) and no subsampling
+            channels.extend(2i32.to_le_bytes());
+            channels.extend([0; 4]);
+            channels.extend(1i32.to_le_bytes());
+            channels.extend(1i32.to_le_bytes());
+        }
+        channels.push(0);
+        let window: Vec<_> = [0, 0, self.width as i32 - 1, self.height as i32 - 1]
+            .iter()
+            .flat_map(|n| n.to_le_bytes())
+            .collect();
+
+        let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+            for s in [name, kind] {
+                header.extend(s.as_bytes());
+                header.push(0);
+            }
+            header.extend((value.len() as i32).to_le_bytes());
+            header.extend(value);
+        };
+        attribute("channels", "chlist", &channels);
+        attribute("compression", "compression", &[0]);
+        attribute("dataWindow", "box2i", &window);
+        attribute("displayWindow", "box2i", &window);
+        attribute("lineOrder", "lineOrder", &[0]);
+        attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute("screenWindowCenter", "v2f", &[0; 8]);
+        attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+        header.push(0);
+
+        // Every scanline is its y coordinate and size followed by the data
+        let line_size = 3 * 4 * self.width;
+        let first_line = header.len() + 8 * self.height;
+        for y in 0..self.height {
+            let offset = first_line + y * (8 + line_size);
+            header.extend((offset as u64).to_le_bytes());
+        }
+        writer.write_all(&header)?;
+
+        for (y, row) in self.rows().enumerate() {
+            let mut line = Vec::with_capacity(8 + line_size);
+            line.extend((y as i32).to_le_bytes());
+            line.extend((line_size as i32).to_le_bytes());
+            for channel in [|p: &Vec3| p.z, |p: &Vec3| p.y, |p: &Vec3| p.x] {
+                for pixel in row {
+                    line.extend(channel(pixel).to_le_bytes());
+                }
+            }
+            writer.write_all(&line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the bytes of an image file
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn peek(&self, len: usize) -> Result<&'a [u8], String> {
+        self.0
+            .get(..len)
+            .ok_or_else(|| "unexpected end of file".to_string())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.peek(len)?;
+        self.0 = &self.0[len..];
+        Ok(bytes)
+    }
+
+    /// Checks that at least `len` bytes are left, before allocating anything
+    /// for a size read from a header
+    fn expect(&self, len: Option<usize>) -> Result<(), String> {
+        match len {
+            Some(len) if len <= self.0.len() => Ok(()),
+            _ => Err("the image is larger than the file".to_string()),
+        }
+    }
+
+    /// Takes a line of text, without the newline
+    fn line(&mut self) -> Result<&'a str, String> {
+        let len = self
+            .0
+            .iter()
+            .position(|&b| b == b'\n')
+            .unwrap_or(self.0.len());
+        let line = self.take(len)?;
+        self.0 = self.0.get(1..).unwrap_or_default();
+        std::str::from_utf8(line).map_err(|_| "invalid header".to_string())
+    }
+
+    /// Takes a word of text and the single whitespace character after it
+    fn word(&mut self) -> Result<&'a str, String> {
+        while self.0.first().map_or(false, u8::is_ascii_whitespace) {
+            self.0 = &self.0[1..];
+        }
+        let len = self
+            .0
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .ok_or_else(|| "unexpected end of file".to_string())?;
+        let word = self.take(len)?;
+        self.0 = &self.0[1..];
+        std::str::from_utf8(word).map_err(|_| "invalid header".to_string())
+    }
+}
+
+fn parse_size(word: &str) -> Result<usize, String> {
+    word.parse()
+        .map_err(|_| format!("expected a size, found `{}`", word))
+}
+
+/// Converts a color in the shared exponent format to floats
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::zero();
+    }
+    let scale = 2f32.powi(e as i32 - (128 + 8));
+    Vec3::new(r as f32, g as f32, b as f32) * scale
+}
+
+/// The brightest channel the shared exponent format can store
+const MAX_RGBE: f32 = 255. * (1u128 << 119) as f32;
+
+/// Converts a color to the shared exponent format used by Radiance files.
+/// Colors too bright to be stored, including infinity, become `MAX_RGBE`, and
+/// NaN becomes 0.
+fn rgbe(color: Vec3) -> [u8; 4] {
+    let color = color.map(|c| {
+        if c.is_nan() {
+            0.
+        } else {
+            c.clamp(0., MAX_RGBE)
+        }
+    });
+    let max = color.reduce_partial_max();
+    if max < 1e-32 {
+        return [0; 4];
+    }
+    // `max` is `mantissa * 2^exponent` with the mantissa in 0.5..1
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256. / 2f32.powi(exponent);
+    [
+        (color.x * scale).min(255.) as u8,
+        (color.y * scale).min(255.) as u8,
+        (color.z * scale).min(255.) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgbe() {
+        assert_eq!(rgbe(Vec3::new(1., 0.5, 0.)), [128, 64, 0, 129]);
+        assert_eq!(rgbe(Vec3::new(0.75, 3., -1.)), [48, 192, 0, 130]);
+        assert_eq!(rgbe(Vec3::zero()), [0; 4]);
+        assert_eq!(
+            rgbe(Vec3::new(f32::INFINITY, f32::NAN, 0.)),
+            [255, 0, 0, 255]
+        );
+        assert_eq!(from_rgbe([255, 0, 0, 255]).x, MAX_RGBE);
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let data = vec![
+            Vec3::new(1., 0.5, 0.),
+            Vec3::new(0.75, 3., 0.),
+            Vec3::zero(),
+            Vec3::new(0.125, 0.25, 16.),
+        ];
+        let image = Image::new(data.clone(), 2, 2);
+
+        let mut pfm = vec![];
+        image.write_pfm(&mut pfm).unwrap();
+        assert_eq!(Image::read_pfm(&pfm).unwrap().data, data);
+
+        // Wide enough to be run length encoded
+        let wide = Image::new([&data[..]; 4].concat(), 8, 2);
+        for image in [image, wide] {
+            let mut hdr = vec![];
+            image.write_hdr(&mut hdr).unwrap();
+            let read = Image::read_hdr(&hdr).unwrap();
+            assert_eq!((read.width, read.height), (image.width, image.height));
+            assert_eq!(read.data, image.data);
+        }
+    }
+
+    #[test]
+    fn test_read_too_large() {
+        let huge = usize::MAX / 2;
+        let pfm = format!("PF\n{} {}\n-1.0\n", huge, huge);
+        assert!(Image::read_pfm(pfm.as_bytes()).is_err());
+        let pfm = format!("PF\n{} 1\n-1.0\n", huge);
+        assert!(Image::read_pfm(pfm.as_bytes()).is_err());
+
+        for width in [4, 100, huge] {
+            let hdr = format!("#?RADIANCE\n\n-Y 1000000 +X {}\n", width);
+            assert!(Image::read_hdr(hdr.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_write_exr() {
+        let image = Image::new(vec![Vec3::new(1., 2., 3.), Vec3::new(4., 5., 6.)], 2, 1);
+        let mut exr = vec![];
+        image.write_exr(&mut exr).unwrap();
+        assert_eq!(exr[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        // The only scanline comes last, right after the offset pointing to it
+        let start = exr.len() - (8 + 3 * 4 * 2);
+        assert_eq!(exr[start - 8..start], (start as u64).to_le_bytes());
+        assert_eq!(exr[start..start + 8], [0, 0, 0, 0, 24, 0, 0, 0]);
+        let channels: Vec<_> = exr[start + 8..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(channels, [3., 6., 2., 5., 1., 4.]);
+    }
+
+    #[test]
+    fn test_display_transform() {
+        let transform = DisplayTransform {
+            exposure: 1.,
+            tone_mapping: ToneMapping::ReinhardExtended { white: 2. },
+            srgb: false,
+        };
+        let white = transform.apply(Vec3::one()).x;
+        assert!((white - 1.).abs() < 1e-6, "{}", white);
+        assert_eq!(transform.apply(Vec3::zero()), Vec3::zero());
+
+        let srgb = DisplayTransform::new();
+        let mid = srgb.apply(Vec3::broadcast(0.2)).x;
+        assert!((mid - 0.4845).abs() < 1e-3, "{}", mid);
+        let image = Image::new(vec![Vec3::broadcast(5.)], 1, 1);
+        assert_eq!(image.get_raw_data(&srgb), [255; 4]);
+    }
 }
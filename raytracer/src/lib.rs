@@ -1,22 +1,38 @@
 #![feature(option_result_contains, test)]
 
+pub mod background;
+pub mod bvh;
 pub mod camera;
 pub mod error;
+pub mod filter;
 pub mod image;
 pub mod lights;
 pub mod material;
+pub mod microfacet;
+pub mod obj;
 pub mod presets;
 pub mod render;
+pub mod scene;
 pub mod shapes;
+pub mod texture;
 
+use std::path::{Path, PathBuf};
+
+use rand::Rng;
+
+pub use background::Background;
+pub use bvh::Bvh;
 pub use camera::Camera;
 pub use error::Error;
-pub use image::Image;
-pub use lights::Light;
+pub use image::{DisplayTransform, Image};
+pub use lights::{Light, LightColor};
 pub use material::Material;
-pub use render::render;
+pub use render::{render, ProgressiveRenderer};
+pub use scene::Scene;
 pub use shapes::Shape;
+pub use texture::Texture;
 
+pub type Vec2 = vek::vec::repr_simd::Vec2<f32>;
 pub type Vec3 = vek::vec::repr_simd::Vec3<f32>;
 pub type Vec4 = vek::vec::repr_simd::Vec4<f32>;
 pub type Transform = vek::transform::repr_simd::Transform<f32, f32, f32>;
@@ -37,10 +53,71 @@ fn orthogonal(v: Vec3) -> (Vec3, Vec3) {
     (a, b)
 }
 
+// `count` points in 0..1 × 0..1, one in each cell of a grid that is as square as
+// possible, so that they are spread evenly. they are at random places in the
+// cells if `rng` is `Some`, and in the middle of them otherwise.
+fn stratified(count: usize, mut rng: Option<&mut impl Rng>) -> Vec<Vec2> {
+    // every cell has to be the same size for the points to be unbiased
+    let columns = (1..=(count as f32).sqrt() as usize)
+        .rev()
+        .find(|columns| count % columns == 0)
+        .unwrap_or(1);
+    let rows = count / columns;
+    (0..count)
+        .map(|i| {
+            let jitter = match &mut rng {
+                Some(rng) => Vec2::new(rng.gen(), rng.gen()),
+                None => Vec2::broadcast(0.5),
+            };
+            let cell = Vec2::new((i % columns) as f32, (i / columns) as f32);
+            (cell + jitter) / Vec2::new(columns as f32, rows as f32)
+        })
+        .collect()
+}
+
+// maps a point in 0..1 × 0..1 to the unit disk with Shirley's concentric
+// mapping, which keeps points that are spread evenly spread evenly
+fn concentric_disk(uv: Vec2) -> Vec2 {
+    let p = uv * 2. - Vec2::one();
+    if p.is_approx_zero() {
+        return Vec2::zero();
+    }
+    let (r, angle) = if p.x.abs() > p.y.abs() {
+        (p.x, std::f32::consts::FRAC_PI_4 * p.y / p.x)
+    } else {
+        (
+            p.y,
+            std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * p.x / p.y,
+        )
+    };
+    Vec2::new(angle.cos(), angle.sin()) * r
+}
+
+// resolves a path that another file refers to relative to that file's directory
+fn relative_to(file: &Path, path: impl Into<PathBuf>) -> PathBuf {
+    let path = path.into();
+    match file.parent() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_stratified() {
+        // 6 points make 2 columns and 3 rows
+        let points = stratified(6, None::<&mut rand_pcg::Pcg32>);
+        let rows = [0.5 / 3., 1.5 / 3., 2.5 / 3.];
+        let expected: Vec<_> = rows
+            .iter()
+            .flat_map(|&y| [Vec2::new(0.25, y), Vec2::new(0.75, y)])
+            .collect();
+        assert_eq!(points, expected);
+    }
+
     #[test]
     fn test_orthogonal() {
         for x in VECTORS {
@@ -1,55 +1,330 @@
+use std::f32::consts::TAU;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    orthogonal,
-    shapes::{Intersect, Intersection, Sphere},
-    Ray, Vec3,
+    concentric_disk, orthogonal,
+    shapes::{BoundedPlane, Intersect, Intersection, Sphere},
+    stratified,
+    texture::srgb_to_linear,
+    Ray, Vec2, Vec3,
 };
 
-// Point light
+/// Something that lights up the scene, either from a shape that gives off
+/// light or from infinitely far away
+#[derive(Serialize, Deserialize)]
 pub struct Light {
+    /// Used to look the light up in a `Scene`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     pub intensity: f32,
+    #[serde(default)]
+    pub color: LightColor,
     pub kind: LightKind,
 }
 
+/// The color of a light, independent of its intensity
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LightColor {
+    Rgb(Vec3),
+    /// The color of a black body at the temperature in kelvin, such as 2700
+    /// for a light bulb or 6500 for daylight
+    Kelvin(f32),
+}
+
+impl Default for LightColor {
+    fn default() -> Self {
+        Self::Rgb(Vec3::one())
+    }
+}
+
+impl LightColor {
+    pub fn rgb(self) -> Vec3 {
+        match self {
+            Self::Rgb(rgb) => rgb,
+            Self::Kelvin(kelvin) => kelvin_to_rgb(kelvin),
+        }
+    }
+}
+
+/// Approximates the color of a black body with Tanner Helland's curve fit to
+/// sRGB colors of black bodies, which is white at about 6600 K. The fit only
+/// holds from 1000 to 40000 K, so other temperatures are clamped to that range.
+fn kelvin_to_rgb(kelvin: f32) -> Vec3 {
+    let t = kelvin.clamp(1000., 40000.) / 100.;
+    let srgb = if t <= 66. {
+        Vec3::new(
+            1.,
+            0.39008157 * t.ln() - 0.6318414,
+            if t <= 19. {
+                0.
+            } else {
+                0.5432068 * (t - 10.).ln() - 1.1962541
+            },
+        )
+    } else {
+        Vec3::new(
+            1.2929362 * (t - 60.).powf(-0.13320476),
+            1.1298909 * (t - 60.).powf(-0.07551485),
+            1.,
+        )
+    };
+    srgb.map(|c| srgb_to_linear(c.clamp(0., 1.)))
+}
+
 impl Light {
-    pub fn rays_to(&self, to: Vec3, soft_shadow_resolution: usize) -> Vec<Ray> {
-        let res = soft_shadow_resolution as isize;
+    /// The light given off, which is what is seen when looking at the light
+    pub fn emitted(&self) -> Vec3 {
+        self.color.rgb() * self.intensity
+    }
+
+    /// Shadow rays from `samples` points on the light to `to`, spread over the
+    /// light in a stratified pattern that is jittered if `rng` is `Some`. Lights
+    /// with a size are sampled evenly over the solid angle they cover as seen
+    /// from `to`. Directional lights are infinitely far away, so their rays
+    /// start at `to` and only the direction matters.
+    pub fn rays_to(&self, to: Vec3, samples: usize, rng: Option<&mut impl Rng>) -> Vec<Ray> {
+        let points = stratified(samples, rng);
         let mut rays = vec![];
         match &self.kind {
-            LightKind::Point(sphere) => {
-                let dir_from_center = (to - sphere.center).normalized();
-                for x in (-res..=res).map(|x| x as f32 / res as f32 * sphere.radius) {
-                    for y in (-res..=res).map(|y| y as f32 / res as f32 * sphere.radius) {
-                        let (a, b) = orthogonal(dir_from_center);
-                        let origin = sphere.center + x * a + y * b;
-                        rays.push(Ray::new(origin, (to - origin).normalized()));
-                    }
+            LightKind::Point(sphere) | LightKind::Spot { sphere, .. } => {
+                let to_center = sphere.center - to;
+                let dist = to_center.magnitude();
+                if dist <= sphere.radius {
+                    rays.push(Ray::new(sphere.center, -to_center.normalized()));
+                    return rays;
+                }
+                let axis = to_center / dist;
+                let cos_max = (1. - (sphere.radius / dist).powi(2)).sqrt();
+                for uv in points {
+                    let direction = cone_direction(axis, cos_max, uv);
+                    // Where the direction enters the sphere
+                    let along = direction.dot(to_center);
+                    let half_chord = (sphere.radius.powi(2) - (dist * dist - along * along))
+                        .max(0.)
+                        .sqrt();
+                    let origin = to + direction * (along - half_chord);
+                    rays.push(Ray::new(origin, -direction));
+                }
+            }
+            LightKind::Directional {
+                direction,
+                angular_diameter,
+            } => {
+                let axis = -direction.normalized();
+                let cos_max = (angular_diameter / 2.).cos();
+                for uv in points {
+                    let direction = cone_direction(axis, cos_max, uv);
+                    rays.push(Ray::new(to, -direction));
+                }
+            }
+            LightKind::Rect(rect) => {
+                for uv in points {
+                    let origin =
+                        rect.center + (uv.x * 2. - 1.) * rect.a + (uv.y * 2. - 1.) * rect.b;
+                    rays.push(Ray::new(origin, (to - origin).normalized()));
                 }
             }
             LightKind::Ambient => {}
         }
         rays
     }
-    pub fn lambert(&self, point: Vec3, normal: Vec3) -> f32 {
+
+    /// How likely one of the rays from `rays_to(to, ..)` is to start at
+    /// `on_light`, per unit solid angle as seen from `to`, or `None` for
+    /// lights that rays can't hit. It is infinite inside of a point light.
+    pub fn pdf(&self, to: Vec3, on_light: Vec3) -> Option<f32> {
         match &self.kind {
-            LightKind::Point(sphere) => {
-                let point2light = sphere.center - point;
-                (self.intensity * normal.dot(point2light) / point2light.magnitude_squared()).max(0.)
+            LightKind::Point(sphere) | LightKind::Spot { sphere, .. } => {
+                let dist = (sphere.center - to).magnitude();
+                if dist <= sphere.radius {
+                    return Some(f32::INFINITY);
+                }
+                let cos_max = (1. - (sphere.radius / dist).powi(2)).sqrt();
+                Some(1. / (TAU * (1. - cos_max)))
+            }
+            LightKind::Rect(rect) => {
+                let to_light = on_light - to;
+                let cos = rect.normal().dot(to_light.normalized()).abs();
+                let area = rect.a.cross(rect.b).magnitude() * 4.;
+                Some(to_light.magnitude_squared() / (area * cos))
             }
-            LightKind::Ambient => self.intensity,
+            LightKind::Directional { .. } | LightKind::Ambient => None,
         }
     }
+
+    /// The light reaching `point` on a surface that faces the light
+    pub fn irradiance(&self, point: Vec3) -> Vec3 {
+        let facing = match &self.kind {
+            LightKind::Point(sphere) | LightKind::Spot { sphere, .. } => sphere.center - point,
+            LightKind::Rect(rect) => rect.center - point,
+            LightKind::Directional { direction, .. } => -*direction,
+            LightKind::Ambient => Vec3::unit_y(),
+        };
+        self.lambert(point, facing.normalized())
+    }
+
+    pub fn lambert(&self, point: Vec3, normal: Vec3) -> Vec3 {
+        let point_lambert = |center: Vec3| {
+            let point2light = center - point;
+            (normal.dot(point2light) / point2light.magnitude_squared()).max(0.)
+        };
+        let lambert = match &self.kind {
+            LightKind::Point(sphere) => point_lambert(sphere.center),
+            LightKind::Spot {
+                sphere,
+                direction,
+                angle,
+                falloff,
+            } => {
+                let light2point = (point - sphere.center).normalized();
+                let off_axis = light2point.angle_between(direction.normalized());
+                // Fades out smoothly from `angle - falloff` to `angle`
+                let t = ((angle - off_axis) / falloff.max(f32::EPSILON)).clamp(0., 1.);
+                point_lambert(sphere.center) * t * t * (3. - 2. * t)
+            }
+            LightKind::Directional { direction, .. } => {
+                (-normal.dot(direction.normalized())).max(0.)
+            }
+            LightKind::Rect(rect) => {
+                let light2point = (point - rect.center).normalized();
+                point_lambert(rect.center) * rect.normal().dot(light2point).max(0.)
+            }
+            LightKind::Ambient => 1.,
+        };
+        self.emitted() * lambert
+    }
+}
+
+/// A direction at most `acos(cos_max)` from `axis`, spread evenly over the
+/// solid angle of the cone as `uv` covers 0..1 in both directions. The middle
+/// of `uv` maps to the axis.
+fn cone_direction(axis: Vec3, cos_max: f32, uv: Vec2) -> Vec3 {
+    // The concentric mapping keeps the stratification of the square
+    let disk = concentric_disk(uv);
+    let r = disk.magnitude();
+    if r == 0. {
+        return axis;
+    }
+    let cos = 1. - r * r * (1. - cos_max);
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    let (a, b) = orthogonal(axis);
+    axis * cos + (a * disk.x + b * disk.y) * (sin / r)
 }
 
 impl Intersect for Light {
     fn intersection(&self, ray: Ray, ignore_normal: Option<Vec3>) -> Option<Intersection> {
-        match self.kind {
-            LightKind::Point(sphere) => sphere.intersection(ray, ignore_normal),
-            LightKind::Ambient => None,
+        match &self.kind {
+            LightKind::Point(sphere) | LightKind::Spot { sphere, .. } => {
+                sphere.intersection(ray, ignore_normal)
+            }
+            LightKind::Rect(rect) => rect.intersection(ray, ignore_normal),
+            LightKind::Directional { .. } | LightKind::Ambient => None,
         }
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub enum LightKind {
     Point(Sphere),
+    /// Light from infinitely far away, like the sun. The shadows are as soft
+    /// as the light is wide, which is about 0.0093 radians for the sun.
+    Directional {
+        /// The direction that the light travels in
+        direction: Vec3,
+        angular_diameter: f32,
+    },
+    /// A point light that only shines in a cone around `direction`
+    Spot {
+        sphere: Sphere,
+        direction: Vec3,
+        /// The angle between `direction` and the edge of the cone, in radians
+        angle: f32,
+        /// The angle inside the edge over which the light fades out
+        falloff: f32,
+    },
+    /// A rectangle that shines on the side that its normal points to
+    Rect(BoundedPlane),
     Ambient,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_pcg::Pcg32;
+
+    #[test]
+    fn test_light_kinds() {
+        let up = Vec3::unit_y();
+        let light = |kind| Light {
+            name: None,
+            intensity: 1.,
+            color: LightColor::default(),
+            kind,
+        };
+
+        let sun = light(LightKind::Directional {
+            direction: -up,
+            angular_diameter: 0.1,
+        });
+        assert_eq!(sun.lambert(Vec3::zero(), up), Vec3::one());
+        assert_eq!(sun.lambert(Vec3::zero(), -up), Vec3::zero());
+        let mut rng = Pcg32::seed_from_u64(0);
+        let rays = sun.rays_to(Vec3::zero(), 9, Some(&mut rng));
+        assert_eq!(rays.len(), 9);
+        for ray in rays {
+            assert_eq!(ray.origin, Vec3::zero());
+            assert!(ray.direction.angle_between(-up) <= 0.05 + 1e-3);
+        }
+
+        let spot = light(LightKind::Spot {
+            sphere: Sphere {
+                center: up,
+                radius: 0.1,
+            },
+            direction: -up,
+            angle: 0.5,
+            falloff: 0.1,
+        });
+        assert_eq!(spot.lambert(Vec3::zero(), up), Vec3::one());
+        // Shadow rays start on the side of the bulb facing the point
+        for ray in spot.rays_to(Vec3::zero(), 16, Some(&mut rng)) {
+            assert!(((ray.origin - up).magnitude() - 0.1).abs() < 1e-4);
+            assert!(ray.origin.y < 1.);
+        }
+        // Outside of the cone, and halfway through the falloff
+        assert_eq!(spot.lambert(Vec3::new(1., 0., 0.), up), Vec3::zero());
+        let edge = Vec3::new(0.45f32.tan(), 0., 0.);
+        let half = spot.lambert(edge, up)
+            / light(LightKind::Point(Sphere {
+                center: up,
+                radius: 0.1,
+            }))
+            .lambert(edge, up);
+        assert!((half.x - 0.5).abs() < 1e-3, "{}", half);
+
+        let rect = light(LightKind::Rect(BoundedPlane {
+            center: up,
+            a: Vec3::unit_x(),
+            b: Vec3::unit_z(),
+        }));
+        // It only shines downwards
+        assert_eq!(rect.lambert(Vec3::zero(), up), Vec3::one());
+        assert_eq!(rect.lambert(up * 2., -up), Vec3::zero());
+        assert!(rect
+            .intersection(Ray::new(Vec3::zero(), up), None)
+            .is_some());
+    }
+
+    #[test]
+    fn test_kelvin() {
+        let candle = LightColor::Kelvin(1900.).rgb();
+        let daylight = LightColor::Kelvin(6600.).rgb();
+        let sky = LightColor::Kelvin(15000.).rgb();
+        assert!(candle.x > candle.y && candle.y > candle.z);
+        assert!((daylight - Vec3::one()).map(f32::abs).reduce_partial_max() < 0.05);
+        assert!(sky.z > sky.y && sky.y > sky.x);
+    }
+}
@@ -1,4 +1,8 @@
-use crate::Vec3;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{orthogonal, shapes::Intersection, Error, Texture, Vec2, Vec3};
 
 pub mod refractive_indices {
     pub const AIR: f32 = 1.000293;
@@ -6,23 +10,296 @@ pub mod refractive_indices {
     pub const GLASS: f32 = 1.458;
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Material {
     pub color: Vec3,
     pub specularity: f32,
     pub roughness: f32,
     pub opacity: f32,
     pub refractive_index: f32,
+    /// Multiplies `color`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_map: Option<Texture>,
+    /// Multiplies `specularity` by the average of its channels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub specularity_map: Option<Texture>,
+    /// Multiplies `roughness` by the average of its channels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roughness_map: Option<Texture>,
+    /// Normals relative to the surface, with the color (0.5, 0.5, 1) pointing
+    /// straight out of it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<Texture>,
+    /// Heights that the surface appears to be offset by, using the average of
+    /// the channels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump_map: Option<Texture>,
+    /// How much `bump_map` tilts the normal
+    #[serde(default = "default_bump_strength")]
+    pub bump_strength: f32,
+    #[serde(default)]
+    pub shading: ShadingModel,
+    /// Light given off from both sides of the surface, which makes the shape
+    /// an area light
+    #[serde(default)]
+    pub emission: Vec3,
+}
+
+/// How a material splits light between its colors, reflection and refraction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ShadingModel {
+    /// Blends the color with refraction by `opacity`, and the result with
+    /// reflection by `specularity`
+    Blend,
+    /// Glass, water and the like. Reflection and refraction are split by the
+    /// Fresnel equations, ignoring `opacity` and `specularity`, and `color`
+    /// tints refracted light.
+    Dielectric {
+        /// How much of each channel is absorbed per unit of distance travelled
+        /// inside the material
+        #[serde(default)]
+        absorption: Vec3,
+    },
+    /// The metallic/roughness model of glTF, using `color` as the base color
+    /// and `roughness` as the perceptual roughness, and ignoring `specularity`,
+    /// `opacity` and `refractive_index`
+    Microfacet {
+        #[serde(default)]
+        metallic: f32,
+    },
+}
+
+impl Default for ShadingModel {
+    fn default() -> Self {
+        Self::Blend
+    }
+}
+
+impl ShadingModel {
+    /// The fraction of light that is left after travelling `dist` inside
+    /// the material, following the Beer–Lambert law
+    pub fn transmittance(&self, dist: f32) -> Vec3 {
+        match self {
+            Self::Blend | Self::Microfacet { .. } => Vec3::one(),
+            Self::Dielectric { absorption } => (-*absorption * dist).map(f32::exp),
+        }
+    }
+}
+
+fn default_bump_strength() -> f32 {
+    1.
 }
 
+/// The step used to find the slope of bump maps
+const BUMP_DELTA: f32 = 1e-3;
+
 impl Material {
     pub fn color(color: Vec3) -> Self {
         Self {
             color,
+            ..Default::default()
+        }
+    }
+
+    pub fn color_at(&self, intersection: &Intersection) -> Vec3 {
+        match &self.color_map {
+            Some(map) => self.color * map.sample(intersection),
+            None => self.color,
+        }
+    }
+
+    pub fn specularity_at(&self, intersection: &Intersection) -> f32 {
+        match &self.specularity_map {
+            Some(map) => self.specularity * map.sample(intersection).average(),
+            None => self.specularity,
+        }
+    }
+
+    pub fn roughness_at(&self, intersection: &Intersection) -> f32 {
+        match &self.roughness_map {
+            Some(map) => self.roughness * map.sample(intersection).average(),
+            None => self.roughness,
+        }
+    }
+
+    /// Replaces the shading normal with the one from `normal_map` and tilts it
+    /// by the slope of `bump_map`
+    pub fn perturb_normal(&self, intersection: &mut Intersection) {
+        if self.normal_map.is_none() && self.bump_map.is_none() {
+            return;
+        }
+        let normal = intersection.normal;
+        let mut tangent = intersection.tangent - normal * normal.dot(intersection.tangent);
+        if tangent.is_approx_zero() {
+            tangent = orthogonal(normal).0;
+        }
+        let tangent = tangent.normalized();
+        let bitangent = normal.cross(tangent);
+
+        let mut perturbed = normal;
+        if let Some(map) = &self.normal_map {
+            let n = map.sample(intersection) * 2. - Vec3::one();
+            perturbed = tangent * n.x + bitangent * n.y + normal * n.z;
+        }
+        if let Some(map) = &self.bump_map {
+            let height = |du: f32, dv: f32| {
+                let uv = intersection.uv + Vec2::new(du, dv);
+                let point = intersection.point + tangent * du + bitangent * dv;
+                map.sample_at(uv, point).average()
+            };
+            let h = height(0., 0.);
+            let slope_u = (height(BUMP_DELTA, 0.) - h) / BUMP_DELTA;
+            let slope_v = (height(0., BUMP_DELTA) - h) / BUMP_DELTA;
+            perturbed -= (tangent * slope_u + bitangent * slope_v) * self.bump_strength;
+        }
+        if !perturbed.is_approx_zero() {
+            intersection.normal = perturbed.normalized();
+        }
+    }
+
+    /// Loads the images of textures that were deserialized, with paths
+    /// relative to `file`
+    pub fn load_textures(&mut self, file: &Path) -> Result<(), Error> {
+        // Only colors are usually sRGB encoded
+        for (map, srgb) in [
+            (&mut self.color_map, true),
+            (&mut self.specularity_map, false),
+            (&mut self.roughness_map, false),
+            (&mut self.normal_map, false),
+            (&mut self.bump_map, false),
+        ] {
+            if let Some(map) = map {
+                map.load_images(file, srgb)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            color: Vec3::one(),
             specularity: 0.,
             roughness: 0.,
             opacity: 1.,
             refractive_index: 1.,
+            color_map: None,
+            specularity_map: None,
+            roughness_map: None,
+            normal_map: None,
+            bump_map: None,
+            bump_strength: 1.,
+            shading: ShadingModel::Blend,
+            emission: Vec3::zero(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{texture::TextureSpace, Image, Ray};
+
+    #[test]
+    fn test_perturb_normal() {
+        let up = Vec3::unit_z();
+        let intersection = || Intersection {
+            ray: Ray::new(Vec3::unit_z(), -Vec3::unit_z()),
+            dist: 1.,
+            point: Vec3::zero(),
+            normal: up,
+            geometric_normal: up,
+            uv: Vec2::zero(),
+            tangent: Vec3::unit_x(),
+        };
+
+        // A flat normal map keeps the normal
+        let flat = Texture::Checker {
+            a: Vec3::new(0.5, 0.5, 1.),
+            b: Vec3::new(0.5, 0.5, 1.),
+            size: 1.,
+            space: TextureSpace::Uv,
+        };
+        let mut material = Material {
+            normal_map: Some(flat),
+            ..Default::default()
+        };
+        let mut i = intersection();
+        material.perturb_normal(&mut i);
+        assert!((i.normal - up).is_approx_zero());
+
+        // The normal tilts away from where the bumps get higher
+        material.normal_map = None;
+        material.bump_map = Some(Texture::Gradient {
+            a: Vec3::zero(),
+            b: Vec3::one(),
+            from: Vec3::new(-1., 0., 0.),
+            to: Vec3::new(1., 0., 0.),
+            space: TextureSpace::World,
+        });
+        let mut i = intersection();
+        material.perturb_normal(&mut i);
+        assert!(i.normal.x < -0.1 && i.normal.y.abs() < 1e-3 && i.normal.z > 0.);
+        assert_eq!(i.geometric_normal, up);
+    }
+
+    #[test]
+    fn test_dielectric() {
+        let intersection = |direction: Vec3| Intersection {
+            ray: Ray::new(Vec3::zero(), direction.normalized()),
+            dist: 1.,
+            point: Vec3::zero(),
+            normal: Vec3::unit_z(),
+            geometric_normal: Vec3::unit_z(),
+            uv: Vec2::zero(),
+            tangent: Vec3::unit_x(),
+        };
+        // Head on, glass reflects about 4% of the light
+        let head_on = intersection(-Vec3::unit_z()).reflectance(1.5);
+        assert!((head_on - 0.04).abs() < 1e-3, "{}", head_on);
+        // Grazing rays are mostly reflected
+        assert!(intersection(Vec3::new(1., 0., -0.01)).reflectance(1.5) > 0.9);
+        // Past the critical angle on the inside, everything is reflected
+        assert_eq!(intersection(Vec3::new(1., 0., 0.5)).reflectance(1.5), 1.);
+        assert!(intersection(Vec3::new(0., 0., 1.)).reflectance(1.5) < 0.05);
+
+        let shading = ShadingModel::Dielectric {
+            absorption: Vec3::new(0., 1., 2.),
+        };
+        let transmittance = shading.transmittance(0.5);
+        assert_eq!(transmittance.x, 1.);
+        assert!((transmittance.y - (-0.5f32).exp()).abs() < 1e-6);
+        assert!(transmittance.z < transmittance.y);
+        assert_eq!(ShadingModel::Blend.transmittance(10.), Vec3::one());
+    }
+
+    #[test]
+    fn test_only_color_maps_are_srgb() {
+        let path = std::env::temp_dir().join("im-already-raytracer-gray.pfm");
+        let image = Image::new(vec![Vec3::broadcast(0.5)], 1, 1);
+        image
+            .write_pfm(std::fs::File::create(&path).unwrap())
+            .unwrap();
+        let map = |fields: &str| -> Option<Texture> {
+            Some(ron::from_str(&format!("Image((path: {:?}{}))", path, fields)).unwrap())
+        };
+
+        let mut material = Material {
+            color_map: map(""),
+            roughness_map: map(""),
+            bump_map: map(", srgb: Some(true)"),
+            ..Default::default()
+        };
+        material.load_textures(Path::new("scene.ron")).unwrap();
+        let sample = |map: &Option<Texture>| {
+            map.as_ref()
+                .unwrap()
+                .sample_at(Vec2::zero(), Vec3::zero())
+                .x
+        };
+        assert!((sample(&material.color_map) - 0.214).abs() < 1e-3);
+        assert_eq!(sample(&material.roughness_map), 0.5);
+        assert!((sample(&material.bump_map) - 0.214).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,191 @@
+use std::f32::consts::{PI, TAU};
+
+use rand::Rng;
+
+use crate::{orthogonal, Vec3};
+
+/// The GGX microfacet BRDF with the metallic/roughness parameters used by glTF
+/// and most game engines. Directions point away from the surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Microfacet {
+    pub base_color: Vec3,
+    /// The width of the distribution of microfacet normals, which is the
+    /// square of the perceptual roughness
+    pub alpha: f32,
+    pub metallic: f32,
+}
+
+/// How much light dielectrics reflect head on
+const DIELECTRIC_REFLECTANCE: f32 = 0.04;
+
+/// Keeps perfectly smooth surfaces from dividing by zero
+const MIN_ALPHA: f32 = 1e-3;
+
+impl Microfacet {
+    pub fn new(base_color: Vec3, roughness: f32, metallic: f32) -> Self {
+        Self {
+            base_color,
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+            metallic: metallic.clamp(0., 1.),
+        }
+    }
+
+    /// The color reflected head on, which is the base color for metals
+    pub fn f0(&self) -> Vec3 {
+        Vec3::lerp(
+            Vec3::broadcast(DIELECTRIC_REFLECTANCE),
+            self.base_color,
+            self.metallic,
+        )
+    }
+
+    /// The Schlick approximation of the Fresnel equations
+    pub fn fresnel(&self, cos: f32) -> Vec3 {
+        let f0 = self.f0();
+        f0 + (Vec3::one() - f0) * (1. - cos.clamp(0., 1.)).powi(5)
+    }
+
+    /// The color of the diffuse light, which is what isn't reflected
+    /// specularly and isn't absorbed by metal
+    pub fn diffuse_color(&self, normal: Vec3, outgoing: Vec3) -> Vec3 {
+        self.base_color * (1. - self.metallic) * (Vec3::one() - self.fresnel(normal.dot(outgoing)))
+    }
+
+    /// The distribution of microfacet normals
+    pub fn distribution(&self, normal: Vec3, half: Vec3) -> f32 {
+        let cos = normal.dot(half);
+        if cos <= 0. {
+            return 0.;
+        }
+        let a2 = self.alpha * self.alpha;
+        let d = cos * cos * (a2 - 1.) + 1.;
+        a2 / (PI * d * d)
+    }
+
+    /// The Smith masking-shadowing term, the fraction of microfacets that are
+    /// visible from both directions
+    pub fn geometry(&self, normal: Vec3, outgoing: Vec3, incoming: Vec3) -> f32 {
+        let g1 = |v: Vec3| {
+            let cos = normal.dot(v).max(0.);
+            let a2 = self.alpha * self.alpha;
+            2. * cos / (cos + (a2 + (1. - a2) * cos * cos).sqrt()).max(f32::EPSILON)
+        };
+        g1(outgoing) * g1(incoming)
+    }
+
+    /// The specular part of the BRDF
+    pub fn specular(&self, normal: Vec3, outgoing: Vec3, incoming: Vec3) -> Vec3 {
+        let cos_o = normal.dot(outgoing);
+        let cos_i = normal.dot(incoming);
+        if cos_o <= 0. || cos_i <= 0. {
+            return Vec3::zero();
+        }
+        let half = (outgoing + incoming).normalized();
+        self.fresnel(outgoing.dot(half))
+            * self.distribution(normal, half)
+            * self.geometry(normal, outgoing, incoming)
+            / (4. * cos_o * cos_i)
+    }
+
+    /// How likely `sample_specular` is to pick `incoming`, per unit solid angle
+    pub fn specular_pdf(&self, normal: Vec3, outgoing: Vec3, incoming: Vec3) -> f32 {
+        let half = (outgoing + incoming).normalized();
+        let cos_h = outgoing.dot(half);
+        if cos_h <= 0. {
+            return 0.;
+        }
+        self.distribution(normal, half) * normal.dot(half) / (4. * cos_h)
+    }
+
+    /// Picks a direction to reflect `outgoing` in, with mirror reflection
+    /// if `rng` is `None`. Returns the direction and the reflected color,
+    /// which is the specular BRDF times the cosine divided by the probability
+    /// of picking the direction, or `None` if it points into the surface.
+    pub fn sample_specular(
+        &self,
+        normal: Vec3,
+        outgoing: Vec3,
+        rng: Option<&mut impl Rng>,
+    ) -> Option<(Vec3, Vec3)> {
+        let half = match rng {
+            Some(rng) => {
+                // Microfacet normals distributed like `distribution(..) * cos`
+                let u: f32 = rng.gen();
+                let angle = rng.gen::<f32>() * TAU;
+                let cos2 = (1. - u) / (1. + (self.alpha * self.alpha - 1.) * u);
+                let (cos, sin) = (cos2.sqrt(), (1. - cos2).max(0.).sqrt());
+                let (a, b) = orthogonal(normal);
+                (a * sin * angle.cos() + b * sin * angle.sin() + normal * cos).normalized()
+            }
+            None => normal,
+        };
+        let cos_h = outgoing.dot(half);
+        let incoming = half * 2. * cos_h - outgoing;
+        let cos_o = normal.dot(outgoing);
+        if cos_h <= 0. || cos_o <= 0. || normal.dot(incoming) <= 0. {
+            return None;
+        }
+        let weight = self.fresnel(cos_h) * self.geometry(normal, outgoing, incoming) * cos_h
+            / (cos_o * normal.dot(half));
+        Some((incoming, weight))
+    }
+
+    /// How likely it is that a path should continue specularly rather than
+    /// diffusely, based on how much light each of them carries
+    pub fn specular_probability(&self, normal: Vec3, outgoing: Vec3) -> f32 {
+        let specular = self.fresnel(normal.dot(outgoing)).average();
+        let diffuse = self.diffuse_color(normal, outgoing).average();
+        if specular + diffuse > 0. {
+            (specular / (specular + diffuse)).clamp(0.1, 1.)
+        } else {
+            1.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_pcg::Pcg32;
+
+    #[test]
+    fn test_sampling_matches_brdf() {
+        // Averaging the sampled weights integrates the BRDF, which doing it
+        // uniformly over the hemisphere should agree with
+        let mut rng = Pcg32::seed_from_u64(0);
+        let normal = Vec3::unit_z();
+        let outgoing = Vec3::new(0.5, 0., 1.).normalized();
+        let material = Microfacet::new(Vec3::new(1., 0.5, 0.2), 0.5, 1.);
+        let samples = 200_000;
+
+        let mut sampled = Vec3::zero();
+        for _ in 0..samples {
+            if let Some((incoming, weight)) =
+                material.sample_specular(normal, outgoing, Some(&mut rng))
+            {
+                sampled += weight;
+                let expected = material.specular(normal, outgoing, incoming) * normal.dot(incoming)
+                    / material.specular_pdf(normal, outgoing, incoming);
+                assert!((weight - expected).map(f32::abs).reduce_partial_max() < 1e-3);
+            }
+        }
+        let mut uniform = Vec3::zero();
+        for _ in 0..samples {
+            let cos: f32 = rng.gen();
+            let sin = (1. - cos * cos).sqrt();
+            let angle = rng.gen::<f32>() * TAU;
+            let incoming = Vec3::new(sin * angle.cos(), sin * angle.sin(), cos);
+            uniform += material.specular(normal, outgoing, incoming) * cos * TAU;
+        }
+        let (sampled, uniform) = (sampled / samples as f32, uniform / samples as f32);
+        assert!(
+            (sampled - uniform).map(f32::abs).reduce_partial_max() < 0.02,
+            "{} {}",
+            sampled,
+            uniform
+        );
+        // A metal reflects no more than its color
+        assert!(sampled.x <= 1. && sampled.y < 0.55 && sampled.z < 0.25);
+    }
+}
@@ -0,0 +1,364 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use crate::{
+    material::ShadingModel,
+    relative_to,
+    shapes::{Mesh, Shape, ShapeKind},
+    texture::{ImageTexture, Texture},
+    Error, Material, Vec2, Vec3,
+};
+
+/// Loads the faces in an OBJ file as one mesh per material. Material
+/// libraries are looked up relative to the OBJ file.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Shape>, Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|err| Error::file(path, err))?;
+    parse_obj(&text, path)
+}
+
+/// Loads the materials in an MTL file
+pub fn load_materials(path: impl AsRef<Path>) -> Result<HashMap<String, Material>, Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|err| Error::file(path, err))?;
+    parse_mtl(&text, path)
+}
+
+fn default_material() -> Material {
+    Material::color(Vec3::one())
+}
+
+/// A group of faces in the same object, sharing a material
+struct Group {
+    name: Option<String>,
+    material: Material,
+    indices: Vec<[usize; 3]>,
+    /// The line of the first face
+    line: usize,
+    smooth: bool,
+    textured: bool,
+}
+
+fn parse_obj(source: &str, path: &Path) -> Result<Vec<Shape>, Error> {
+    let mut positions = vec![];
+    let mut uvs = vec![];
+    let mut normals = vec![];
+
+    // Vertices in OBJ files have separate position, texture coordinate and
+    // normal indices, but a mesh has a single index per vertex, so every
+    // distinct combination becomes a vertex of its own
+    let mut vertices = vec![];
+    let mut vertex_uvs = vec![];
+    let mut vertex_normals = vec![];
+    let mut vertex_lookup: HashMap<(usize, Option<usize>, Option<usize>), usize> = HashMap::new();
+
+    let mut materials = HashMap::new();
+    let mut material = default_material();
+    let mut object = None;
+    let mut groups: Vec<Group> = vec![];
+    // Index of the group that faces are added to, if one has been started for
+    // the current object and material
+    let mut current = None;
+
+    for (line_i, line) in source.lines().enumerate() {
+        let line_no = line_i + 1;
+        let error = |message: String| Error::parse(path, line_no, message);
+        let mut words = line.split('#').next().unwrap().split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<_> = words.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&args).map_err(error)?),
+            "vt" => uvs.push(parse_vec2(&args).map_err(error)?),
+            "vn" => normals.push(parse_vec3(&args).map_err(error)?.normalized()),
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(format!(
+                        "a face needs at least 3 vertices, found {}",
+                        args.len()
+                    )));
+                }
+                if current.is_none() {
+                    groups.push(Group {
+                        name: object.clone(),
+                        material: material.clone(),
+                        indices: vec![],
+                        line: line_no,
+                        smooth: true,
+                        textured: true,
+                    });
+                    current = Some(groups.len() - 1);
+                }
+                let group = &mut groups[current.unwrap()];
+
+                let mut face = Vec::with_capacity(args.len());
+                for arg in args {
+                    let (position, uv, normal) =
+                        parse_face_vertex(arg, positions.len(), uvs.len(), normals.len())
+                            .map_err(error)?;
+                    let key = (position, uv, normal);
+                    let index = *vertex_lookup.entry(key).or_insert_with(|| {
+                        vertices.push(positions[position]);
+                        vertex_uvs.push(uv.map_or(Vec2::zero(), |t| uvs[t]));
+                        vertex_normals.push(normal.map_or(Vec3::zero(), |n| normals[n]));
+                        vertices.len() - 1
+                    });
+                    group.smooth &= normal.is_some();
+                    group.textured &= uv.is_some();
+                    face.push(index);
+                }
+                // Triangulate the polygon as a fan, which works for convex ones
+                for i in 1..face.len() - 1 {
+                    group.indices.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "mtllib" => {
+                // The file name may contain spaces
+                materials.extend(load_materials(relative_to(path, args.join(" ")))?);
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                material = materials
+                    .get(&name)
+                    .ok_or_else(|| error(format!("unknown material `{}`", name)))?
+                    .clone();
+                current = None;
+            }
+            "o" => {
+                object = Some(args.join(" "));
+                current = None;
+            }
+            // Groups, smoothing groups etc. don't matter here
+            _ => {}
+        }
+    }
+
+    let vertices = Arc::new(vertices);
+    let vertex_uvs = Arc::new(vertex_uvs);
+    let vertex_normals = Arc::new(vertex_normals);
+    groups
+        .into_iter()
+        .filter(|group| !group.indices.is_empty())
+        .map(|group| {
+            let mesh = Mesh::new(
+                Arc::clone(&vertices),
+                group.smooth.then(|| Arc::clone(&vertex_normals)),
+                group.textured.then(|| Arc::clone(&vertex_uvs)),
+                group.indices,
+            )
+            .map_err(|message| Error::parse(path, group.line, message))?;
+            Ok(Shape {
+                name: group.name,
+                material: group.material,
+                kind: ShapeKind::Mesh(mesh),
+            })
+        })
+        .collect()
+}
+
+fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, Material>, Error> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Material)> = None;
+
+    for (line_i, line) in source.lines().enumerate() {
+        let line_no = line_i + 1;
+        let error = |message: String| Error::parse(path, line_no, message);
+        let mut words = line.split('#').next().unwrap().split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<_> = words.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((args.join(" "), default_material()));
+            continue;
+        }
+        let material = match &mut current {
+            Some((_, material)) => material,
+            None => return Err(error(format!("`{}` before any `newmtl`", keyword))),
+        };
+        match keyword {
+            "Kd" => material.color = parse_vec3(&args).map_err(error)?,
+            "Ke" => material.emission = parse_vec3(&args).map_err(error)?,
+            "Ks" => material.specularity = parse_vec3(&args).map_err(error)?.average(),
+            "Ns" => {
+                // Convert the Phong exponent to a roughness in 0..=1
+                let exponent = parse_f32(&args).map_err(error)?.max(0.);
+                material.roughness = (2. / (exponent + 2.)).sqrt();
+            }
+            // The PBR extension specifies roughness directly
+            "Pr" => material.roughness = parse_f32(&args).map_err(error)?,
+            "Pm" => {
+                material.shading = ShadingModel::Microfacet {
+                    metallic: parse_f32(&args).map_err(error)?,
+                }
+            }
+            "d" => material.opacity = parse_f32(&args).map_err(error)?,
+            "Tr" => material.opacity = 1. - parse_f32(&args).map_err(error)?,
+            "Ni" => material.refractive_index = parse_f32(&args).map_err(error)?,
+            "map_Kd" | "map_Ks" | "map_Pr" | "norm" | "bump" | "map_Bump" => {
+                // Options such as `-s` come before the file name, which is
+                // therefore assumed to be the last argument
+                let file = args
+                    .last()
+                    .ok_or_else(|| error("expected a file".to_string()))?;
+                // Only color images are sRGB encoded
+                let srgb = keyword == "map_Kd";
+                let texture = Texture::Image(ImageTexture::load(relative_to(path, file), srgb)?);
+                match keyword {
+                    "map_Kd" => material.color_map = Some(texture),
+                    "map_Ks" => material.specularity_map = Some(texture),
+                    "map_Pr" => material.roughness_map = Some(texture),
+                    "norm" => material.normal_map = Some(texture),
+                    _ => material.bump_map = Some(texture),
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+fn parse_number(word: &str) -> Result<f32, String> {
+    word.parse()
+        .map_err(|_| format!("expected a number, found `{}`", word))
+}
+
+fn parse_f32(args: &[&str]) -> Result<f32, String> {
+    match args {
+        [x, ..] => parse_number(x),
+        [] => Err("expected a number".to_string()),
+    }
+}
+
+/// Parses the first two numbers, since texture coordinates may have a third
+fn parse_vec2(args: &[&str]) -> Result<Vec2, String> {
+    match args {
+        [x, y, ..] => Ok(Vec2::new(parse_number(x)?, parse_number(y)?)),
+        [x] => Ok(Vec2::new(parse_number(x)?, 0.)),
+        [] => Err("expected 2 numbers, found 0".to_string()),
+    }
+}
+
+fn parse_vec3(args: &[&str]) -> Result<Vec3, String> {
+    match args {
+        [x, y, z, ..] => Ok(Vec3::new(
+            parse_number(x)?,
+            parse_number(y)?,
+            parse_number(z)?,
+        )),
+        _ => Err(format!("expected 3 numbers, found {}", args.len())),
+    }
+}
+
+/// Parses an OBJ index, which is 1-based or, if negative, relative to the end
+fn parse_index(word: &str, len: usize) -> Result<usize, String> {
+    let index: isize = word
+        .parse()
+        .map_err(|_| format!("expected an index, found `{}`", word))?;
+    let resolved = if index < 0 {
+        len as isize + index
+    } else {
+        index - 1
+    };
+    if 0 <= resolved && (resolved as usize) < len {
+        Ok(resolved as usize)
+    } else {
+        Err(format!("index {} is out of bounds", index))
+    }
+}
+
+/// Parses a face vertex on the form `v`, `v/vt`, `v//vn` or `v/vt/vn` into
+/// its position, texture coordinate and normal indices
+fn parse_face_vertex(
+    word: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = word.split('/');
+    let position = parse_index(parts.next().unwrap(), position_count)?;
+    let uv = match parts.next() {
+        Some(uv) if !uv.is_empty() => Some(parse_index(uv, uv_count)?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(normal) if !normal.is_empty() => Some(parse_index(normal, normal_count)?),
+        _ => None,
+    };
+    Ok((position, uv, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_obj() {
+        let source = "
+            # a quad with normals and a triangle without
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vn 0 0 2
+            f 1//1 2//1 3//1 4//1
+            f -4 -3 -2
+        ";
+        let shapes = parse_obj(source, Path::new("test.obj")).unwrap();
+        assert_eq!(shapes.len(), 1);
+        let mesh = match &shapes[0].kind {
+            ShapeKind::Mesh(mesh) => mesh,
+            kind => panic!("expected a mesh, got {:?}", kind),
+        };
+        assert_eq!(mesh.indices().len(), 3);
+        // The triangle doesn't have normals, so the mesh can't be smooth
+        assert!(mesh.normals().is_none());
+        // The quad's vertices have normals while the triangle's don't
+        assert_eq!(mesh.vertices().len(), 7);
+
+        match parse_obj("v 0 0 0\nf 1 2 3", Path::new("test.obj")) {
+            Err(Error::ParseError { line, .. }) => assert_eq!(line, 2),
+            result => panic!("expected a parse error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_parse_mtl() {
+        let source = "
+            newmtl glass
+            Kd 1 1 0.5
+            Ks 0.1 0.1 0.1
+            d 0.5
+            Ni 1.5
+
+            newmtl gold
+            Kd 1 0.8 0.3
+            Pr 0.2
+            Pm 1
+
+            newmtl lamp
+            Ke 4 4 3
+        ";
+        let materials = parse_mtl(source, Path::new("test.mtl")).unwrap();
+        let glass = &materials["glass"];
+        assert_eq!(glass.color, Vec3::new(1., 1., 0.5));
+        assert!((glass.specularity - 0.1).abs() < 1e-6);
+        assert_eq!(glass.opacity, 0.5);
+        assert_eq!(glass.refractive_index, 1.5);
+        let gold = &materials["gold"];
+        assert_eq!(gold.roughness, 0.2);
+        assert_eq!(gold.shading, ShadingModel::Microfacet { metallic: 1. });
+        assert_eq!(materials["lamp"].emission, Vec3::new(4., 4., 3.));
+    }
+}
@@ -1,15 +1,24 @@
 use crate::{
     camera::MappingFunction,
-    lights::LightKind,
+    lights::{LightColor, LightKind},
     material::refractive_indices,
     shapes::{BoundedPlane, Plane, Shape, ShapeKind, Sphere},
-    Camera, Light, Material, Quaternion, Vec3,
+    Camera, Light, Material, Quaternion, Scene, Vec3,
 };
 
-pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
-    let mut shapes = vec![];
+pub fn cornellbox() -> Scene {
+    let mut scene = Scene::new(Camera {
+        position: Vec3::new(0., 0., 15.),
+        orientation: Quaternion::identity(),
+        fov: 45f32.to_radians(),
+        mapping_function: MappingFunction::Linear,
+        aperture: 0.,
+        focus_distance: 15.,
+        aperture_blades: None,
+    });
     // ground
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("ground".to_string()),
         material: {
             Material {
                 color: Vec3::new(1., 1., 1.),
@@ -17,6 +26,7 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
                 roughness: 0.2,
                 opacity: 1.,
                 refractive_index: 1.,
+                ..Default::default()
             }
         },
         kind: ShapeKind::Plane(Plane {
@@ -25,7 +35,8 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
         }),
     });
     // red
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("red wall".to_string()),
         material: {
             Material {
                 color: Vec3::new(1., 0., 0.),
@@ -33,6 +44,7 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
                 roughness: 0.2,
                 opacity: 1.,
                 refractive_index: 1.,
+                ..Default::default()
             }
         },
         kind: ShapeKind::BoundedPlane(BoundedPlane {
@@ -42,7 +54,8 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
         }),
     });
     // green
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("green wall".to_string()),
         material: {
             Material {
                 color: Vec3::new(0., 1., 0.),
@@ -50,6 +63,7 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
                 roughness: 0.2,
                 opacity: 1.,
                 refractive_index: 1.,
+                ..Default::default()
             }
         },
         kind: ShapeKind::BoundedPlane(BoundedPlane {
@@ -59,7 +73,8 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
         }),
     });
     // back
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("back wall".to_string()),
         material: {
             Material {
                 color: Vec3::new(1., 1., 0.8),
@@ -67,6 +82,7 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
                 roughness: 0.2,
                 opacity: 1.,
                 refractive_index: 1.,
+                ..Default::default()
             }
         },
         kind: ShapeKind::BoundedPlane(BoundedPlane {
@@ -76,7 +92,8 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
         }),
     });
     // roof
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("roof".to_string()),
         material: {
             Material {
                 color: Vec3::new(1., 1., 1.),
@@ -84,6 +101,7 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
                 roughness: 0.5,
                 opacity: 1.,
                 refractive_index: 1.,
+                ..Default::default()
             }
         },
         kind: ShapeKind::BoundedPlane(BoundedPlane {
@@ -93,7 +111,8 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
         }),
     });
     // blue ball
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("blue ball".to_string()),
         material: {
             Material {
                 color: Vec3::new(0., 0., 1.),
@@ -101,6 +120,7 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
                 roughness: 0.04,
                 opacity: 1.,
                 refractive_index: 1.,
+                ..Default::default()
             }
         },
         kind: ShapeKind::Sphere(Sphere {
@@ -109,7 +129,8 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
         }),
     });
     // glass ball
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("glass ball".to_string()),
         material: {
             Material {
                 color: Vec3::new(1., 1., 0.),
@@ -117,6 +138,7 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
                 roughness: 0.,
                 opacity: 0.5,
                 refractive_index: refractive_indices::AIR + 1.9,
+                ..Default::default()
             }
         },
         kind: ShapeKind::Sphere(Sphere {
@@ -124,35 +146,38 @@ pub fn cornellbox() -> (Camera, Vec<Shape>, Vec<Light>) {
             radius: 2.,
         }),
     });
-    (
-        Camera {
-            position: Vec3::new(0., 0., 15.),
-            orientation: Quaternion::identity(),
-            fov: 45f32.to_radians(),
-            mapping_function: MappingFunction::Linear,
-        },
-        shapes,
-        vec![
-            Light {
-                intensity: 7.,
-                kind: LightKind::Point(Sphere {
-                    center: Vec3::new(0., 4.5, 0.),
-                    radius: 0.5,
-                }),
-            },
-            Light {
-                intensity: 0.05,
-                kind: LightKind::Ambient,
-            },
-        ],
-    )
+    scene.add_light(Light {
+        name: Some("lamp".to_string()),
+        intensity: 7.,
+        color: LightColor::default(),
+        kind: LightKind::Point(Sphere {
+            center: Vec3::new(0., 4.5, 0.),
+            radius: 0.5,
+        }),
+    });
+    scene.add_light(Light {
+        name: Some("ambient".to_string()),
+        intensity: 0.05,
+        color: LightColor::default(),
+        kind: LightKind::Ambient,
+    });
+    scene
 }
 
-pub fn stick_in_water() -> (Camera, Vec<Shape>, Vec<Light>) {
-    let mut shapes = vec![];
+pub fn stick_in_water() -> Scene {
+    let mut scene = Scene::new(Camera {
+        position: Vec3::new(0., -3., 0.),
+        orientation: Quaternion::rotation_3d(30f32.to_radians(), Vec3::unit_x()),
+        fov: 70f32.to_radians(),
+        mapping_function: MappingFunction::Linear,
+        aperture: 0.,
+        focus_distance: 5.,
+        aperture_blades: None,
+    });
 
     // water
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("water".to_string()),
         material: {
             Material {
                 color: Vec3::new(0., 0.16, 0.23),
@@ -160,6 +185,7 @@ pub fn stick_in_water() -> (Camera, Vec<Shape>, Vec<Light>) {
                 roughness: 0.05,
                 opacity: 0.3,
                 refractive_index: refractive_indices::WATER,
+                ..Default::default()
             }
         },
         kind: ShapeKind::Plane(Plane {
@@ -168,26 +194,29 @@ pub fn stick_in_water() -> (Camera, Vec<Shape>, Vec<Light>) {
         }),
     });
     // stick
-    shapes.append(&mut make_box(
+    for shape in make_box(
         Vec3::new(1., -2., -5.),
         Quaternion::rotation_3d(45f32.to_radians(), Vec3::unit_z()),
         &Material::color(Vec3::new(0.67, 0.5, 0.29)),
         Vec3::new(0.2, 5., 0.2),
-    ));
-    (
-        Camera {
-            position: Vec3::new(0., -3., 0.),
-            orientation: Quaternion::rotation_3d(30f32.to_radians(), Vec3::unit_x()),
-            fov: 70f32.to_radians(),
-            mapping_function: MappingFunction::Linear,
-        },
-        shapes,
-        vec![],
-    )
+    ) {
+        scene.add_shape(shape);
+    }
+    scene
 }
 
-pub fn light_and_box() -> (Camera, Vec<Shape>, Vec<Light>) {
-    let mut shapes = make_box(
+pub fn light_and_box() -> Scene {
+    let mut scene = Scene::new(Camera {
+        position: Vec3::new(0., 0., 0.),
+        orientation: Quaternion::identity(),
+        fov: 70f32.to_radians(),
+        mapping_function: MappingFunction::Linear,
+        aperture: 0.,
+        focus_distance: 5.,
+        aperture_blades: None,
+    });
+
+    for shape in make_box(
         Vec3::new(0., 0., -5.),
         Quaternion::identity(),
         &Material {
@@ -196,17 +225,22 @@ pub fn light_and_box() -> (Camera, Vec<Shape>, Vec<Light>) {
             roughness: 0.8,
             opacity: 1.,
             refractive_index: 1.,
+            ..Default::default()
         },
         Vec3::one(),
-    );
+    ) {
+        scene.add_shape(shape);
+    }
 
-    shapes.push(Shape {
+    scene.add_shape(Shape {
+        name: Some("floor".to_string()),
         material: Material {
             color: Vec3::new(1., 1., 1.),
             specularity: 0.5,
             roughness: 0.8,
             opacity: 1.,
             refractive_index: 1.,
+            ..Default::default()
         },
         kind: ShapeKind::BoundedPlane(BoundedPlane {
             center: Vec3::new(0., -3., -5.),
@@ -215,22 +249,16 @@ pub fn light_and_box() -> (Camera, Vec<Shape>, Vec<Light>) {
         }),
     });
 
-    (
-        Camera {
-            position: Vec3::new(0., 0., 0.),
-            orientation: Quaternion::identity(),
-            fov: 70f32.to_radians(),
-            mapping_function: MappingFunction::Linear,
-        },
-        shapes,
-        vec![Light {
-            intensity: 3.,
-            kind: LightKind::Point(Sphere {
-                center: Vec3::new(0., 5., -5.),
-                radius: 1.,
-            }),
-        }],
-    )
+    scene.add_light(Light {
+        name: Some("lamp".to_string()),
+        intensity: 3.,
+        color: LightColor::default(),
+        kind: LightKind::Point(Sphere {
+            center: Vec3::new(0., 5., -5.),
+            radius: 1.,
+        }),
+    });
+    scene
 }
 
 pub fn make_box(
@@ -242,6 +270,7 @@ pub fn make_box(
     vec![
         // +x
         Shape {
+            name: None,
             material: material.clone(),
             kind: ShapeKind::BoundedPlane(BoundedPlane {
                 center: center + orientation * Vec3::unit_x() * size.x,
@@ -251,6 +280,7 @@ pub fn make_box(
         },
         // -x
         Shape {
+            name: None,
             material: material.clone(),
             kind: ShapeKind::BoundedPlane(BoundedPlane {
                 center: center - orientation * Vec3::unit_x() * size.x,
@@ -260,6 +290,7 @@ pub fn make_box(
         },
         // +y
         Shape {
+            name: None,
             material: material.clone(),
             kind: ShapeKind::BoundedPlane(BoundedPlane {
                 center: center + orientation * Vec3::unit_y() * size.y,
@@ -269,6 +300,7 @@ pub fn make_box(
         },
         // -y
         Shape {
+            name: None,
             material: material.clone(),
             kind: ShapeKind::BoundedPlane(BoundedPlane {
                 center: center - orientation * Vec3::unit_y() * size.y,
@@ -278,6 +310,7 @@ pub fn make_box(
         },
         // +z
         Shape {
+            name: None,
             material: material.clone(),
             kind: ShapeKind::BoundedPlane(BoundedPlane {
                 center: center + orientation * Vec3::unit_z() * size.z,
@@ -287,6 +320,7 @@ pub fn make_box(
         },
         // -z
         Shape {
+            name: None,
             material: material.clone(),
             kind: ShapeKind::BoundedPlane(BoundedPlane {
                 center: center - orientation * Vec3::unit_z() * size.z,
@@ -1,89 +1,451 @@
-use std::ops::Range;
+use std::{
+    f32::consts::{PI, TAU},
+    thread,
+};
+#[cfg(feature = "parallel")]
+use std::{iter, ops::Range};
 
+#[cfg(feature = "parallel")]
+use crossbeam::{
+    channel,
+    deque::{Injector, Steal, Stealer, Worker},
+};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
 use vek::Lerp;
 
 use crate::{
+    bvh::Bvh,
     camera::Rays,
-    shapes::{Intersect, Intersection, Shape},
-    Camera, Image, Light, Ray, Vec3,
+    filter::{Filter, FilterSampler},
+    image::ToneMapping,
+    lights::{Light, LightKind},
+    material::ShadingModel,
+    microfacet::Microfacet,
+    orthogonal,
+    shapes::{Intersect, Intersection},
+    stratified, DisplayTransform, Image, Material, Ray, Scene, Shape, Vec2, Vec3,
 };
 
+/// How much closer than the shaded point something must be hit by a shadow
+/// ray to block the light
+const SHADOW_EPSILON: f32 = 1e-3;
+
+/// Pixels darker than this are held to the noise threshold as if they were
+/// this bright, so that nearly black pixels don't use up the whole budget
+const DARK_BRIGHTNESS: f32 = 0.1;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RenderOptions {
-    pub multisampling: usize,
+    /// Rays per pixel, spread over the area around it that `filter` covers.
+    /// With `use_randomness` they are jittered, so that edges converge
+    /// smoothly instead of having steps.
+    pub pixel_samples: usize,
+    pub filter: Filter,
+    /// Keeps sampling pixels that are still noisy after `pixel_samples`
+    pub adaptive: Option<AdaptiveSampling>,
     pub width: usize,
     pub height: usize,
     pub max_ray_depth: usize,
-    pub soft_shadow_resolution: usize,
+    /// Shadow rays per light, spread over it so that shadows are soft. With
+    /// `use_randomness` they are jittered, so that shadows converge smoothly
+    /// with more pixel samples instead of having bands.
+    pub shadow_samples: usize,
     pub use_randomness: bool,
+    /// Clamps every sample to 0..1, which keeps single bright samples from
+    /// speckling the image. Tone mapping needs the colors above 1, so this only
+    /// applies when `display` uses `ToneMapping::Clamp`.
     pub clamp_colors: bool,
+    /// Number of threads to render with when the `parallel` feature is
+    /// enabled. Defaults to the number of threads the machine can run at once.
+    pub threads: usize,
+    /// Seed for the random numbers used when `use_randomness` is true, making
+    /// renders reproducible regardless of `threads`. A random seed is used if
+    /// it is `None`.
+    pub seed: Option<u64>,
+    pub integrator: Integrator,
+    /// Rays sent from diffuse surfaces towards the background to find how
+    /// much it lights them, when using `Integrator::Whitted`
+    pub environment_samples: usize,
+    /// How the image is turned into colors for displays
+    pub display: DisplayTransform,
+}
+
+/// How the color of a ray is computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// Light only comes directly from the lights, plus mirror reflections and
+    /// refractions. Rays stop at `max_ray_depth`.
+    Whitted,
+    /// Monte-Carlo path tracing, where light also bounces between diffuse
+    /// surfaces. Paths are cut short by Russian roulette after
+    /// `max_ray_depth` bounces, and ambient lights are left out since indirect
+    /// light takes their place. Always random, regardless of
+    /// `use_randomness`.
+    PathTracing,
+}
+
+/// Settings for giving noisy pixels more samples than flat ones. Every pixel
+/// gets `RenderOptions::pixel_samples` samples at first, and then as many more
+/// at a time until it is smooth enough.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveSampling {
+    /// How large the standard error of a pixel's brightness may be, relative
+    /// to the brightness
+    pub threshold: f32,
+    /// The most samples a pixel gets
+    pub max_samples: usize,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            threshold: 0.05,
+            max_samples: 64,
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    /// Whether a pixel needs more samples, given how many it has and the sum
+    /// of their brightnesses and of their squares
+    fn is_noisy(&self, count: usize, sum: f32, squared_sum: f32) -> bool {
+        if count < 2 {
+            return true;
+        }
+        let n = count as f32;
+        let mean = sum / n;
+        let variance = ((squared_sum - sum * mean) / (n - 1.)).max(0.);
+        (variance / n).sqrt() > self.threshold * mean.max(DARK_BRIGHTNESS)
+    }
 }
 
 impl Default for RenderOptions {
     fn default() -> Self {
         Self {
-            multisampling: 1,
+            pixel_samples: 1,
+            filter: Filter::Box,
+            adaptive: None,
             width: 640,
             height: 640,
             max_ray_depth: 5,
-            soft_shadow_resolution: 4,
+            shadow_samples: 16,
             use_randomness: true,
             clamp_colors: true,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            seed: None,
+            integrator: Integrator::Whitted,
+            environment_samples: 8,
+            display: DisplayTransform::new(),
         }
     }
 }
 
-pub fn render(
-    options: &RenderOptions,
-    camera: &Camera,
-    shapes: &[Shape],
-    lights: &[Light],
-) -> Image {
+/// Renders the scene. Call `Scene::build_bvh` beforehand to avoid building
+/// the bounding volume hierarchy for every render.
+pub fn render(options: &RenderOptions, scene: &Scene) -> Image {
+    render_with_sample_map(options, scene).0
+}
+
+/// Renders the scene like `render`, along with an image of how many samples
+/// every pixel got with adaptive sampling. It is white where a pixel got as
+/// many as it could.
+pub fn render_with_sample_map(options: &RenderOptions, scene: &Scene) -> (Image, Image) {
     let mut buffer = vec![Vec3::zero(); options.width * options.height];
-    let rays = camera.rays(
-        options.width * options.multisampling,
-        options.height * options.multisampling,
-    );
+    let mut counts = vec![0; buffer.len()];
+    render_pass(options, scene, 0, &mut buffer, &mut counts);
+    let samples = options.pixel_samples as f32;
+    for pixel in &mut buffer {
+        *pixel /= samples;
+    }
+    let max_samples = match options.adaptive {
+        Some(adaptive) => adaptive.max_samples.max(options.pixel_samples),
+        None => options.pixel_samples,
+    };
+    let map = counts
+        .into_iter()
+        .map(|count| Vec3::broadcast(count as f32 / max_samples as f32))
+        .collect();
+    (
+        Image::new(buffer, options.width, options.height),
+        Image::new(map, options.width, options.height),
+    )
+}
+
+/// Accumulates passes over a scene into a steadily less noisy image, so that
+/// a preview can be shown while the render converges
+#[derive(Default)]
+pub struct ProgressiveRenderer {
+    /// The sum of all samples taken for every pixel
+    sum: Vec<Vec3>,
+    width: usize,
+    height: usize,
+    passes: usize,
+    samples: usize,
+}
+
+impl ProgressiveRenderer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            sum: vec![Vec3::zero(); width * height],
+            width,
+            height,
+            passes: 0,
+            samples: 0,
+        }
+    }
+
+    /// Renders the scene once more and adds the result to the estimate. Starts
+    /// over if the size in `options` has changed.
+    pub fn pass(&mut self, options: &RenderOptions, scene: &Scene) {
+        if options.width != self.width || options.height != self.height {
+            *self = Self::new(options.width, options.height);
+        }
+        let mut counts = vec![0; self.sum.len()];
+        render_pass(
+            options,
+            scene,
+            self.passes as u64,
+            &mut self.sum,
+            &mut counts,
+        );
+        self.passes += 1;
+        self.samples += options.pixel_samples;
+    }
+
+    /// Throws away everything rendered so far, e.g. when the camera has moved
+    pub fn reset(&mut self) {
+        self.sum.fill(Vec3::zero());
+        self.passes = 0;
+        self.samples = 0;
+    }
+
+    /// The number of samples taken for every pixel so far
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The current estimate, which is black before the first pass
+    pub fn image(&self) -> Image {
+        let samples = self.samples.max(1) as f32;
+        let data = self.sum.iter().map(|&sum| sum / samples).collect();
+        Image::new(data, self.width, self.height)
+    }
+}
+
+/// Adds the weighted average of the samples for every pixel to `buffer`, times
+/// `pixel_samples`, and the number of samples to `counts`. `pass` makes every
+/// pass get different random numbers.
+fn render_pass(
+    options: &RenderOptions,
+    scene: &Scene,
+    pass: u64,
+    buffer: &mut [Vec3],
+    counts: &mut [usize],
+) {
+    let rays = scene.camera.rays(options.width, options.height);
+    let bvh = scene.bvh();
+    let tracer = Tracer {
+        options,
+        scene,
+        bvh: &bvh,
+        filter: FilterSampler::new(options.filter),
+        emitters: scene
+            .shapes()
+            .iter()
+            .filter(|shape| {
+                shape.material.emission != Vec3::zero() && shape.kind.area().is_finite()
+            })
+            .collect(),
+    };
     #[cfg(feature = "parallel")]
     {
+        // Tiles are handed out as threads become free, so that threads that
+        // get quick tiles (e.g. sky) keep working while others are slow
+        let injector = Injector::new();
+        for tile in tiles(options.width, options.height) {
+            injector.push(tile);
+        }
+        let workers: Vec<_> = (0..options.threads.max(1))
+            .map(|_| Worker::new_fifo())
+            .collect();
+        let stealers: Vec<_> = workers.iter().map(Worker::stealer).collect();
+        let (sender, receiver) = channel::unbounded();
+
         crossbeam::scope(|s| {
-            for (range, output) in split_buffer(&mut buffer, 12) {
-                s.spawn(|_| render_part(options, range, output, &rays, shapes, lights));
+            for worker in workers {
+                let tracer = &tracer;
+                let rays = &rays;
+                let injector = &injector;
+                let stealers = &stealers;
+                let sender = sender.clone();
+                s.spawn(move |_| {
+                    while let Some(tile) = next_tile(&worker, injector, stealers) {
+                        let mut output = vec![Vec3::zero(); tile.len()];
+                        let mut output_counts = vec![0; tile.len()];
+                        render_part(
+                            tracer,
+                            pass,
+                            tile.pixels(options.width),
+                            &mut output,
+                            &mut output_counts,
+                            rays,
+                        );
+                        sender.send((tile, output, output_counts)).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            for (tile, output, output_counts) in receiver {
+                for ((i, color), count) in tile.pixels(options.width).zip(output).zip(output_counts)
+                {
+                    buffer[i] += color;
+                    counts[i] += count;
+                }
             }
         })
         .unwrap();
     }
     #[cfg(not(feature = "parallel"))]
     {
-        render_part(options, 0..buffer.len(), &mut buffer, &rays, shapes, lights);
+        render_part(&tracer, pass, 0..buffer.len(), buffer, counts, &rays);
     }
-    Image::new(buffer, options.width, options.height)
 }
 
+/// Width and height of the tiles that the image is split into for threads
+#[cfg(feature = "parallel")]
+const TILE_SIZE: usize = 16;
+
+/// A rectangle of pixels
+#[cfg(feature = "parallel")]
+struct Tile {
+    x: Range<usize>,
+    y: Range<usize>,
+}
+
+#[cfg(feature = "parallel")]
+impl Tile {
+    fn len(&self) -> usize {
+        self.x.len() * self.y.len()
+    }
+
+    /// The indices of the pixels in the tile, row by row
+    fn pixels(&self, width: usize) -> impl Iterator<Item = usize> + '_ {
+        self.y
+            .clone()
+            .flat_map(move |y| self.x.clone().map(move |x| y * width + x))
+    }
+}
+
+#[cfg(feature = "parallel")]
+fn tiles(width: usize, height: usize) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x: x..(x + TILE_SIZE).min(width),
+                y: y..(y + TILE_SIZE).min(height),
+            });
+        }
+    }
+    tiles
+}
+
+/// Takes a tile from the thread's own queue, the shared queue or another
+/// thread's queue, in that order
+#[cfg(feature = "parallel")]
+fn next_tile(
+    local: &Worker<Tile>,
+    global: &Injector<Tile>,
+    stealers: &[Stealer<Tile>],
+) -> Option<Tile> {
+    local.pop().or_else(|| {
+        iter::repeat_with(|| {
+            global
+                .steal_batch_and_pop(local)
+                .or_else(|| stealers.iter().map(Stealer::steal).collect())
+        })
+        .find(|steal| !steal.is_retry())
+        .and_then(Steal::success)
+    })
+}
+
+/// Adds the samples for the pixels with the given indices to `output` and how
+/// many there were to `counts`, in the same order
 fn render_part(
-    options: &RenderOptions,
-    range: Range<usize>,
+    tracer: &Tracer,
+    pass: u64,
+    pixels: impl Iterator<Item = usize>,
     output: &mut [Vec3],
+    counts: &mut [usize],
     rays: &Rays,
-    shapes: &[Shape],
-    lights: &[Light],
 ) {
-    let ms = options.multisampling;
-    for (out_i, pos_i) in range.enumerate() {
-        let base_x = pos_i % options.width;
-        let base_y = pos_i / options.width;
+    let options = tracer.options;
+    let clamp_colors = options.clamp_colors && options.display.tone_mapping == ToneMapping::Clamp;
+    let mut rng = Pcg32::from_entropy();
+    for (out_i, pos_i) in pixels.enumerate() {
+        if let Some(seed) = options.seed {
+            // A stream per pixel and pass makes seeded renders independent of
+            // how the image is split between threads
+            rng = Pcg32::new(seed, pass << 32 | pos_i as u64);
+        }
+        let pixel = Vec2::new(
+            (pos_i % options.width) as f32,
+            (pos_i / options.width) as f32,
+        );
         let mut color_sum = Vec3::zero();
-        for y in (0..ms).map(|s| base_y * ms + s) {
-            for x in (0..ms).map(|s| base_x * ms + s) {
-                let color = ray_color(options, rays.get(x, y), shapes, lights, 0, None);
-                color_sum += if options.clamp_colors {
+        let mut weight_sum = 0.;
+        let mut count = 0;
+        // Of the brightness of the samples, to tell how noisy the pixel is
+        let mut brightness_sum = 0.;
+        let mut squared_sum = 0.;
+        // Samples are taken in batches that are stratified on their own
+        let mut batch = options.pixel_samples;
+        while batch > 0 {
+            let offsets = stratified(batch, options.use_randomness.then(|| &mut rng));
+            // Stratified on their own and paired up at random, so that both
+            // the pixel and the lens are covered evenly
+            let mut lens = stratified(batch, options.use_randomness.then(|| &mut rng));
+            if options.use_randomness {
+                lens.shuffle(&mut rng);
+            }
+            for (uv, lens) in offsets.into_iter().zip(lens) {
+                let (x, x_weight) = tracer.filter.sample(uv.x);
+                let (y, y_weight) = tracer.filter.sample(uv.y);
+                let ray = rays.get(pixel + Vec2::new(x, y), lens);
+                let color = match options.integrator {
+                    Integrator::Whitted => tracer.ray_color(ray, 0, None, &mut rng),
+                    Integrator::PathTracing => tracer.path_color(ray, &mut rng),
+                };
+                let color = if clamp_colors {
                     clamp_color(color)
                 } else {
                     color
                 };
+                color_sum += color * x_weight * y_weight;
+                weight_sum += x_weight * y_weight;
+                brightness_sum += color.average();
+                squared_sum += color.average().powi(2);
             }
+            count += batch;
+            batch = match options.adaptive {
+                Some(adaptive) if adaptive.is_noisy(count, brightness_sum, squared_sum) => {
+                    batch.min(adaptive.max_samples.saturating_sub(count))
+                }
+                _ => 0,
+            };
         }
-        let pixel_color = color_sum / (ms * ms) as f32;
-        output[out_i] = pixel_color;
+        counts[out_i] += count;
+        // Dividing by the weights that were actually drawn keeps negative
+        // lobes from speckling flat areas. They can only add up to less than
+        // 0 when nearly all of a few samples land in the lobes.
+        output[out_i] += if weight_sum > 0. {
+            color_sum / weight_sum * options.pixel_samples as f32
+        } else {
+            color_sum * options.pixel_samples as f32 / count as f32
+        };
     }
 }
 
@@ -96,116 +458,582 @@ fn clamp_color(color: Vec3) -> Vec3 {
     )
 }
 
-fn split_buffer<'a, T>(mut buffer: &'a mut [T], parts: usize) -> Vec<(Range<usize>, &'a mut [T])> {
-    let mut v = Vec::with_capacity(parts);
-    let orig_len = buffer.len();
-    let mut start = 0;
-    for p in (1..=parts).rev() {
-        let (a, b) = buffer.split_at_mut(buffer.len() / p);
-        buffer = b;
-        let end = start + a.len();
-        v.push((start..end, a));
-        start = end;
-    }
-    assert_eq!(v[0].0.start, 0);
-    for i in 1..parts {
-        assert_eq!(v[i - 1].0.end, v[i].0.start, "{}", i);
-    }
-    assert_eq!(v[parts - 1].0.end, orig_len);
-    assert_eq!(buffer.len(), 0);
-    v
+/// Everything needed to find the color of a ray
+struct Tracer<'a> {
+    options: &'a RenderOptions,
+    scene: &'a Scene,
+    bvh: &'a Bvh,
+    filter: FilterSampler,
+    /// Shapes with an emissive material, which are sampled like lights
+    emitters: Vec<&'a Shape>,
 }
 
-fn ray_color(
-    options: &RenderOptions,
-    ray: Ray,
-    shapes: &[Shape],
-    lights: &[Light],
-    depth: usize,
-    ignore_normal: Option<Vec3>,
-) -> Vec3 {
-    if depth == options.max_ray_depth {
-        return Vec3::zero(); // todo: something better
-    }
+impl Tracer<'_> {
+    fn ray_color(
+        &self,
+        ray: Ray,
+        depth: usize,
+        ignore_normal: Option<Vec3>,
+        rng: &mut Pcg32,
+    ) -> Vec3 {
+        let Tracer {
+            options,
+            scene,
+            bvh,
+            ..
+        } = *self;
+        let shapes = scene.shapes();
+        let lights = scene.lights();
 
-    let (shape, intersection) = match ray_intersection(ray, shapes.iter(), ignore_normal) {
-        Some(shape_intersection) => shape_intersection,
-        None => return Vec3::zero(), // todo: skybox
-    };
+        if depth == options.max_ray_depth {
+            return Vec3::zero(); // todo: something better
+        }
 
-    if let Some((light, light_intersection)) = ray_intersection(ray, lights.iter(), None) {
-        // TODO: what if theyre equal? maybe check normal?
-        if light_intersection.dist < intersection.dist {
-            return Vec3::broadcast(light.intensity);
+        let (shape, mut intersection) = match bvh.intersection(ray, shapes, ignore_normal) {
+            Some(shape_intersection) => shape_intersection,
+            None => return scene.background.color(ray.direction),
+        };
+
+        if let Some((light, light_intersection)) = ray_intersection(ray, lights.iter(), None) {
+            // TODO: what if theyre equal? maybe check normal?
+            if light_intersection.dist < intersection.dist {
+                return light.emitted();
+            }
+        };
+
+        let mat = &shape.material;
+        let front = ray.direction.dot(intersection.geometric_normal) < 0.;
+        mat.perturb_normal(&mut intersection);
+        let specularity = mat.specularity_at(&intersection);
+        let roughness = mat.roughness_at(&intersection);
+
+        if let ShadingModel::Dielectric { .. } = mat.shading {
+            let reflectance = intersection.reflectance(mat.refractive_index);
+            let reflection_color = if reflectance > 0. {
+                self.ray_color(
+                    intersection.reflection(roughness, options.use_randomness.then(|| &mut *rng)),
+                    depth + 1,
+                    Some(intersection.geometric_normal),
+                    rng,
+                )
+            } else {
+                Vec3::zero()
+            };
+            let refraction_color = if reflectance < 1. {
+                let tint = if front {
+                    mat.color_at(&intersection)
+                } else {
+                    Vec3::one()
+                };
+                tint * self.ray_color(
+                    intersection.refraction(mat.refractive_index),
+                    depth + 1,
+                    Some(intersection.geometric_normal),
+                    rng,
+                )
+            } else {
+                Vec3::zero()
+            };
+            return mat.emission
+                + absorption(mat, &intersection, front)
+                    * Lerp::lerp(refraction_color, reflection_color, reflectance);
+        }
+        if let ShadingModel::Microfacet { metallic } = mat.shading {
+            let microfacet = Microfacet::new(mat.color_at(&intersection), roughness, metallic);
+            let outgoing = -ray.direction;
+            let normal = if front {
+                intersection.normal
+            } else {
+                -intersection.normal
+            };
+            let (direct, direct_specular) = self.direct_light(
+                ray,
+                &intersection,
+                true,
+                Some(&microfacet),
+                options.use_randomness.then(|| &mut *rng),
+            );
+            let environment =
+                self.environment_light(&intersection, options.use_randomness.then(|| &mut *rng));
+            let diffuse_color = microfacet.diffuse_color(normal, outgoing) * (direct + environment);
+            let specular_color = match microfacet.sample_specular(
+                normal,
+                outgoing,
+                options.use_randomness.then(|| &mut *rng),
+            ) {
+                Some((direction, weight)) if depth + 1 < options.max_ray_depth => {
+                    let reflected = Ray::new(intersection.point, direction);
+                    match self.light_hit(reflected, Some(intersection.geometric_normal)) {
+                        Some((light, light_intersection)) => {
+                            let specular_pdf = microfacet.specular_pdf(normal, outgoing, direction);
+                            weight
+                                * self.specular_hit(
+                                    light,
+                                    intersection.point,
+                                    light_intersection.point,
+                                    specular_pdf,
+                                )
+                        }
+                        None => {
+                            weight
+                                * self.ray_color(
+                                    reflected,
+                                    depth + 1,
+                                    Some(intersection.geometric_normal),
+                                    rng,
+                                )
+                        }
+                    }
+                }
+                _ => Vec3::zero(),
+            };
+            return mat.emission + diffuse_color + direct_specular + specular_color;
         }
-    };
 
-    let mat = &shape.material;
+        let reflection_color = if specularity > 0. {
+            self.ray_color(
+                intersection.reflection(roughness, options.use_randomness.then(|| &mut *rng)),
+                depth + 1,
+                Some(intersection.geometric_normal),
+                rng,
+            )
+        } else {
+            Vec3::zero()
+        };
+        let refraction_color = if mat.opacity < 1. {
+            self.ray_color(
+                intersection.refraction(mat.refractive_index),
+                depth + 1,
+                Some(intersection.geometric_normal),
+                rng,
+            )
+        } else {
+            Vec3::zero()
+        };
+
+        let (direct, _) = self.direct_light(
+            ray,
+            &intersection,
+            true,
+            None,
+            options.use_randomness.then(|| &mut *rng),
+        );
+        let environment =
+            self.environment_light(&intersection, options.use_randomness.then(|| &mut *rng));
+        let matt_color = mat.color_at(&intersection) * (direct + environment);
 
-    let reflection_color = if mat.specularity > 0. {
-        ray_color(
+        mat.emission
+            + Lerp::lerp(
+                Lerp::lerp(refraction_color, matt_color, mat.opacity),
+                reflection_color,
+                if front { specularity } else { 0. },
+            )
+    }
+
+    /// Follows a path of bounces from the ray, picking one of the ways the
+    /// material scatters light at random at every bounce
+    fn path_color(&self, mut ray: Ray, rng: &mut Pcg32) -> Vec3 {
+        let Tracer {
             options,
-            intersection.reflection(mat.roughness, options.use_randomness),
-            shapes,
-            lights,
-            depth + 1,
-            Some(intersection.normal),
-        )
-    } else {
-        Vec3::zero()
-    };
-    let refraction_color = if mat.opacity < 1. {
-        ray_color(
+            scene,
+            bvh,
+            ..
+        } = *self;
+        let shapes = scene.shapes();
+
+        let mut color = Vec3::zero();
+        // How much of the light found further along the path reaches the camera
+        let mut throughput = Vec3::one();
+        let mut ignore_normal = None;
+        // Light sources are already sampled at diffuse bounces, so hitting them
+        // only counts when the path got here some other way
+        let mut count_lights = true;
+        // How likely the last bounce was to go where it did, if it was a
+        // microfacet reflection, whose light is partly found by sampling
+        let mut specular_pdf = None;
+
+        for depth in 0.. {
+            let (shape, mut intersection) = match bvh.intersection(ray, shapes, ignore_normal) {
+                Some(shape_intersection) => shape_intersection,
+                None => return color + throughput * scene.background.color(ray.direction),
+            };
+
+            if count_lights {
+                if let Some((light, light_intersection)) =
+                    ray_intersection(ray, scene.lights().iter(), None)
+                {
+                    if light_intersection.dist < intersection.dist {
+                        let emitted = match specular_pdf {
+                            Some(pdf) => {
+                                self.specular_hit(light, ray.origin, light_intersection.point, pdf)
+                            }
+                            None => light.emitted(),
+                        };
+                        return color + throughput * emitted;
+                    }
+                }
+            }
+
+            let mat = &shape.material;
+            // Emissive shapes that can't be sampled as lights are only found by
+            // bouncing into them
+            if count_lights || !shape.kind.area().is_finite() {
+                color += throughput * mat.emission;
+            }
+            let front = ray.direction.dot(intersection.geometric_normal) < 0.;
+            mat.perturb_normal(&mut intersection);
+            specular_pdf = None;
+            if let ShadingModel::Dielectric { .. } = mat.shading {
+                throughput *= absorption(mat, &intersection, front);
+                if rng.gen::<f32>() < intersection.reflectance(mat.refractive_index) {
+                    ray = intersection.reflection(mat.roughness_at(&intersection), Some(&mut *rng));
+                } else {
+                    if front {
+                        throughput *= mat.color_at(&intersection);
+                    }
+                    ray = intersection.refraction(mat.refractive_index);
+                }
+                count_lights = true;
+            } else if let ShadingModel::Microfacet { metallic } = mat.shading {
+                let microfacet = Microfacet::new(
+                    mat.color_at(&intersection),
+                    mat.roughness_at(&intersection),
+                    metallic,
+                );
+                let outgoing = -ray.direction;
+                let normal = if front {
+                    intersection.normal
+                } else {
+                    -intersection.normal
+                };
+                let diffuse_color = microfacet.diffuse_color(normal, outgoing);
+                let (direct, direct_specular) = self.direct_light(
+                    ray,
+                    &intersection,
+                    false,
+                    Some(&microfacet),
+                    Some(&mut *rng),
+                );
+                color += throughput * (diffuse_color * direct + direct_specular);
+                let specular_probability = microfacet.specular_probability(normal, outgoing);
+                if rng.gen::<f32>() < specular_probability {
+                    match microfacet.sample_specular(normal, outgoing, Some(&mut *rng)) {
+                        Some((direction, weight)) => {
+                            throughput *= weight / specular_probability;
+                            ray = Ray::new(intersection.point, direction);
+                            specular_pdf =
+                                Some(microfacet.specular_pdf(normal, outgoing, direction));
+                        }
+                        // The reflection went into the surface
+                        None => break,
+                    }
+                    count_lights = true;
+                } else {
+                    throughput *= diffuse_color / (1. - specular_probability);
+                    ray = diffuse_bounce(&intersection, rng);
+                    count_lights = false;
+                }
+            } else if front && rng.gen::<f32>() < mat.specularity_at(&intersection) {
+                ray = intersection.reflection(mat.roughness_at(&intersection), Some(&mut *rng));
+                count_lights = true;
+            } else if rng.gen::<f32>() >= mat.opacity {
+                ray = intersection.refraction(mat.refractive_index);
+                count_lights = true;
+            } else {
+                throughput *= mat.color_at(&intersection);
+                let (direct, _) =
+                    self.direct_light(ray, &intersection, false, None, Some(&mut *rng));
+                color += throughput * direct;
+                ray = diffuse_bounce(&intersection, rng);
+                count_lights = false;
+            }
+            ignore_normal = Some(intersection.geometric_normal);
+
+            if depth >= options.max_ray_depth {
+                // Russian roulette: end dim paths early, and make up for it by
+                // brightening the ones that survive. Even bright paths end
+                // sometimes, so that light trapped inside a shape can't bounce
+                // around forever.
+                let survival = throughput.reduce_partial_max().clamp(0.05, 0.95);
+                if rng.gen::<f32>() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        color
+    }
+
+    /// The light reaching the point from the background, estimated with
+    /// `environment_samples` rays in stratified directions that are jittered if
+    /// `rng` is `Some`
+    fn environment_light(&self, intersection: &Intersection, rng: Option<&mut Pcg32>) -> Vec3 {
+        let samples = self.options.environment_samples;
+        let background = &self.scene.background;
+        if samples == 0 || background.is_black() {
+            return Vec3::zero();
+        }
+        let mut sum = Vec3::zero();
+        for uv in stratified(samples, rng) {
+            let ray = cosine_bounce(intersection, uv);
+            let blocked = self
+                .bvh
+                .intersection(
+                    ray,
+                    self.scene.shapes(),
+                    Some(intersection.geometric_normal),
+                )
+                .is_some();
+            if !blocked {
+                sum += background.color(ray.direction);
+            }
+        }
+        sum / samples as f32
+    }
+
+    /// The light reaching the point from the lights and from emissive shapes,
+    /// weighted by how much of each of them is visible from it, using
+    /// `shadow_samples` stratified samples of each of them that are jittered
+    /// if `rng` is `Some`. Also returns how much of the light from
+    /// the lights `microfacet` reflects specularly towards the ray, which
+    /// emissive shapes are left out of since the reflected ray finds them.
+    fn direct_light(
+        &self,
+        ray: Ray,
+        intersection: &Intersection,
+        ambient: bool,
+        microfacet: Option<&Microfacet>,
+        mut rng: Option<&mut Pcg32>,
+    ) -> (Vec3, Vec3) {
+        let Tracer {
             options,
-            intersection.refraction(mat.refractive_index),
-            shapes,
-            lights,
-            depth + 1,
-            Some(intersection.normal),
-        )
-    } else {
-        Vec3::zero()
-    };
+            scene,
+            bvh,
+            ..
+        } = *self;
+        let shapes = scene.shapes();
+
+        let outgoing = -ray.direction;
+        let normal = if intersection.normal.dot(outgoing) > 0. {
+            intersection.normal
+        } else {
+            -intersection.normal
+        };
 
-    let mut lambert = 0.;
-    for light in lights {
-        let rays = light.rays_to(intersection.point, options.soft_shadow_resolution);
-        let ray_count = rays.len();
-        let mut hits = 0;
-        for r in rays {
-            if let Some((s, _)) = ray_intersection(r, shapes.iter(), None) {
-                // todo: maybe it would be nice to compare the pointers here instead.
-                if *s != *shape {
+        let mut received = Vec3::zero();
+        let mut specular = Vec3::zero();
+        for light in scene.lights() {
+            if let LightKind::Ambient = light.kind {
+                if !ambient {
                     continue;
                 }
+                // Light from everywhere, of which the Fresnel reflectance is
+                // reflected
+                if let Some(microfacet) = microfacet {
+                    specular += microfacet.fresnel(normal.dot(outgoing)) * light.emitted();
+                }
             }
-            let light_side = intersection.normal.dot(r.direction).signum();
-            let watch_side = intersection.normal.dot(ray.direction).signum();
-            if light_side != watch_side {
-                // The light is on the other side of the object
-                continue;
+            let rays = light.rays_to(
+                intersection.point,
+                options.shadow_samples,
+                rng.as_deref_mut(),
+            );
+            let ray_count = rays.len();
+            let mut hits = 0;
+            for r in rays {
+                let shadowed = if let LightKind::Directional { .. } = light.kind {
+                    // Nothing is farther away than the light, so anything
+                    // towards it casts a shadow
+                    let towards = Ray::new(
+                        intersection.point - r.direction * SHADOW_EPSILON,
+                        -r.direction,
+                    );
+                    bvh.intersection(towards, shapes, None).is_some()
+                } else if let Some((_, shadow)) = bvh.intersection(r, shapes, None) {
+                    // Comparing distances rather than shapes, so that meshes can
+                    // shadow themselves
+                    let dist_to_point = (intersection.point - r.origin).magnitude();
+                    shadow.dist < dist_to_point - SHADOW_EPSILON
+                } else {
+                    false
+                };
+                if shadowed {
+                    continue;
+                }
+                let light_side = intersection.normal.dot(r.direction).signum();
+                let watch_side = intersection.normal.dot(ray.direction).signum();
+                if light_side != watch_side {
+                    // The light is on the other side of the object
+                    continue;
+                }
+                hits += 1;
+                if let Some(microfacet) = microfacet {
+                    specular += self.light_specular(
+                        light,
+                        microfacet,
+                        intersection.point,
+                        normal,
+                        outgoing,
+                        r,
+                    ) / ray_count as f32;
+                }
             }
-            hits += 1;
+            let hit_factor = if ray_count > 0 {
+                hits as f32 / ray_count as f32
+            } else {
+                1.
+            };
+            received += light.lambert(intersection.point, intersection.normal) * hit_factor;
         }
-        let hit_factor = if ray_count > 0 {
-            hits as f32 / ray_count as f32
-        } else {
-            1.
+
+        let samples = options.shadow_samples.max(1);
+        // The shape itself is sampled too, since concave shapes can light
+        // themselves. Samples from the rest of it are behind the surface or at
+        // a grazing angle.
+        for &emitter in &self.emitters {
+            // Irradiance from the samples, as a fraction of the emission
+            let mut irradiance = 0.;
+            for uv in stratified(samples, rng.as_deref_mut()) {
+                let (point, normal) = match emitter.kind.sample_surface(uv) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                let to_light = point - intersection.point;
+                let dist = to_light.magnitude();
+                if dist < SHADOW_EPSILON {
+                    // The sample is the point itself
+                    continue;
+                }
+                let direction = to_light / dist;
+                let cos = intersection.normal.dot(direction);
+                if cos * intersection.normal.dot(ray.direction) >= 0. {
+                    // The sample is on the other side of the object
+                    continue;
+                }
+                let shadow_ray = Ray::new(intersection.point, direction);
+                if let Some((_, shadow)) =
+                    bvh.intersection(shadow_ray, shapes, Some(intersection.geometric_normal))
+                {
+                    if shadow.dist < dist - SHADOW_EPSILON {
+                        continue;
+                    }
+                }
+                irradiance += cos.abs() * normal.dot(direction).abs() / (dist * dist);
+            }
+            // Dividing by π makes a white diffuse surface reflect as much as it
+            // would if the light was found by bouncing into it
+            received += emitter.material.emission * irradiance * emitter.kind.area()
+                / (PI * samples as f32);
+        }
+        (received, specular)
+    }
+
+    /// The light from a shadow ray that reached `point`, reflected
+    /// specularly towards `outgoing`. Lights that rays can hit are also found
+    /// by the reflected ray, so the two are weighted to add up to the light
+    /// once.
+    fn light_specular(
+        &self,
+        light: &Light,
+        microfacet: &Microfacet,
+        point: Vec3,
+        normal: Vec3,
+        outgoing: Vec3,
+        shadow_ray: Ray,
+    ) -> Vec3 {
+        let incoming = -shadow_ray.direction;
+        let weight = match light.pdf(point, shadow_ray.origin) {
+            Some(pdf) => power_heuristic(
+                self.options.shadow_samples as f32 * pdf,
+                microfacet.specular_pdf(normal, outgoing, incoming),
+            ),
+            None => 1.,
         };
-        lambert += light.lambert(intersection.point, intersection.normal) * hit_factor;
+        // The π matches how bright the light makes diffuse surfaces, whose
+        // BRDF is divided by it
+        microfacet.specular(normal, outgoing, incoming)
+            * normal.dot(incoming).max(0.)
+            * light.irradiance(point)
+            * PI
+            * weight
     }
 
-    let matt_color = mat.color * lambert;
+    /// The light from a microfacet reflection from `from` that hit `light` at
+    /// `on_light`, given how likely the reflection was to go there. The rest
+    /// of the light is found by sampling the light in `direct_light`.
+    fn specular_hit(&self, light: &Light, from: Vec3, on_light: Vec3, specular_pdf: f32) -> Vec3 {
+        let pdf = match light.pdf(from, on_light) {
+            Some(pdf) => pdf,
+            None => return Vec3::zero(),
+        };
+        let weight = power_heuristic(specular_pdf, self.options.shadow_samples as f32 * pdf);
+        if weight == 0. {
+            return Vec3::zero();
+        }
+        // As bright as the light has to be to give off `irradiance` over the
+        // solid angle that it covers
+        light.irradiance(from) * PI * pdf * weight
+    }
 
-    Lerp::lerp(
-        Lerp::lerp(refraction_color, matt_color, mat.opacity),
-        reflection_color,
-        if ray.direction.dot(intersection.normal) < 0. {
-            mat.specularity
-        } else {
-            0.
-        },
-    )
+    /// The light that the ray hits, if it isn't blocked by a shape
+    fn light_hit(&self, ray: Ray, ignore_normal: Option<Vec3>) -> Option<(&Light, Intersection)> {
+        let (light, light_intersection) = ray_intersection(ray, self.scene.lights().iter(), None)?;
+        match self
+            .bvh
+            .intersection(ray, self.scene.shapes(), ignore_normal)
+        {
+            Some((_, intersection)) if intersection.dist < light_intersection.dist => None,
+            _ => Some((light, light_intersection)),
+        }
+    }
+}
+
+/// How much a sample from a strategy with probability density `pdf` counts
+/// when `other_pdf` is that of another strategy for the same light, which
+/// favors whichever is less noisy
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    if pdf.is_infinite() {
+        return 1.;
+    }
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0. && b.is_finite() {
+        a / (a + b)
+    } else {
+        0.
+    }
+}
+
+/// How much of the light along the ray to the intersection is left, which is
+/// less than all of it when the ray travelled inside an absorbing material
+fn absorption(material: &Material, intersection: &Intersection, front: bool) -> Vec3 {
+    if front {
+        Vec3::one()
+    } else {
+        material.shading.transmittance(intersection.dist)
+    }
+}
+
+/// A random direction on the side of the surface that the ray came from, more
+/// likely close to the normal, as light is scattered by a diffuse surface
+fn diffuse_bounce(intersection: &Intersection, rng: &mut impl Rng) -> Ray {
+    cosine_bounce(intersection, Vec2::new(rng.gen(), rng.gen()))
+}
+
+/// Maps a point in 0..1 × 0..1 to a direction on the side of the surface that
+/// the ray came from. Evenly spread points give cosine weighted directions.
+fn cosine_bounce(intersection: &Intersection, uv: Vec2) -> Ray {
+    let normal = if intersection.ray.direction.dot(intersection.normal) < 0. {
+        intersection.normal
+    } else {
+        -intersection.normal
+    };
+    let (a, b) = orthogonal(normal);
+    let r2 = uv.x;
+    let angle = uv.y * TAU;
+    let r = r2.sqrt();
+    let direction = a * r * angle.cos() + b * r * angle.sin() + normal * (1. - r2).sqrt();
+    Ray::new(intersection.point, direction.normalized())
 }
 
 fn ray_intersection<'s, Intersectable>(
@@ -230,3 +1058,349 @@ where
 
     return closest;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::{Camera, MappingFunction},
+        lights::LightColor,
+        material::ShadingModel,
+        presets,
+        shapes::{BoundedPlane, Mesh, ShapeKind, Sphere},
+        Background, Quaternion,
+    };
+
+    #[test]
+    fn test_path_tracing_furnace() {
+        // A diffuse ball under a uniformly white sky reflects exactly its
+        // albedo, which Russian roulette from the first bounce mustn't change
+        let mut scene = Scene::new(Camera {
+            position: Vec3::new(0., 0., 5.),
+            orientation: Quaternion::identity(),
+            fov: 0.1,
+            mapping_function: MappingFunction::Linear,
+            aperture: 0.,
+            focus_distance: 5.,
+            aperture_blades: None,
+        });
+        scene.background = Background::Solid(Vec3::one());
+        scene.add_shape(Shape {
+            name: None,
+            material: Material::color(Vec3::broadcast(0.5)),
+            kind: ShapeKind::Sphere(Sphere {
+                center: Vec3::zero(),
+                radius: 1.,
+            }),
+        });
+        let options = RenderOptions {
+            width: 8,
+            height: 8,
+            pixel_samples: 256,
+            max_ray_depth: 0,
+            clamp_colors: false,
+            integrator: Integrator::PathTracing,
+            seed: Some(11),
+            ..Default::default()
+        };
+        let image = render(&options, &scene);
+        let mean = image.data.iter().copied().sum::<Vec3>() / image.data.len() as f32;
+        assert!(
+            (mean - Vec3::broadcast(0.5))
+                .map(f32::abs)
+                .reduce_partial_max()
+                < 0.01,
+            "{}",
+            mean
+        );
+    }
+
+    #[test]
+    fn test_concave_emitter_lights_itself() {
+        let mut scene = Scene::new(Camera {
+            position: Vec3::new(0., 0., 5.),
+            orientation: Quaternion::identity(),
+            fov: 0.1,
+            mapping_function: MappingFunction::Linear,
+            aperture: 0.,
+            focus_distance: 5.,
+            aperture_blades: None,
+        });
+        // Two walls at a right angle, facing each other and the camera
+        let mesh = Mesh::new(
+            vec![
+                Vec3::new(-1., -1., 1.),
+                Vec3::new(-1., 1., 1.),
+                Vec3::new(0., -1., 0.),
+                Vec3::new(0., 1., 0.),
+                Vec3::new(1., -1., 1.),
+                Vec3::new(1., 1., 1.),
+            ],
+            None,
+            None,
+            vec![[0, 2, 3], [0, 3, 1], [2, 4, 5], [2, 5, 3]],
+        )
+        .unwrap();
+        scene.add_shape(Shape {
+            name: None,
+            material: Material {
+                emission: Vec3::one(),
+                ..Material::color(Vec3::one())
+            },
+            kind: ShapeKind::Mesh(mesh),
+        });
+        let options = RenderOptions {
+            width: 4,
+            height: 4,
+            max_ray_depth: 1,
+            clamp_colors: false,
+            use_randomness: false,
+            ..Default::default()
+        };
+        let image = render(&options, &scene);
+        // Each wall gets light from the other on top of its own emission
+        for pixel in image.data {
+            assert!(pixel.x > 1.05, "{}", pixel);
+        }
+    }
+
+    #[test]
+    fn test_metal_highlight() {
+        // A small light that reflections are unlikely to hit, so the highlight
+        // has to come from sampling the light
+        let mut scene = Scene::new(Camera {
+            position: Vec3::new(0., 0., 5.),
+            orientation: Quaternion::identity(),
+            fov: 0.5,
+            mapping_function: MappingFunction::Linear,
+            aperture: 0.,
+            focus_distance: 5.,
+            aperture_blades: None,
+        });
+        scene.add_shape(Shape {
+            name: None,
+            material: Material {
+                roughness: 0.4,
+                shading: ShadingModel::Microfacet { metallic: 1. },
+                ..Material::color(Vec3::one())
+            },
+            kind: ShapeKind::Sphere(Sphere {
+                center: Vec3::zero(),
+                radius: 1.,
+            }),
+        });
+        scene.add_light(Light {
+            name: None,
+            intensity: 20.,
+            color: LightColor::default(),
+            kind: LightKind::Point(Sphere {
+                center: Vec3::new(0., 3., 4.),
+                radius: 0.01,
+            }),
+        });
+        for integrator in [Integrator::Whitted, Integrator::PathTracing] {
+            let options = RenderOptions {
+                width: 16,
+                height: 16,
+                max_ray_depth: 2,
+                shadow_samples: 1,
+                use_randomness: false,
+                integrator,
+                seed: Some(1),
+                ..Default::default()
+            };
+            let image = render(&options, &scene);
+            let brightest = image.data.iter().map(|c| c.x).fold(0., f32::max);
+            assert!(brightest > 0.1, "{:?} {}", integrator, brightest);
+        }
+    }
+
+    #[test]
+    fn test_directional_shadows_are_unbounded() {
+        let mut scene = Scene::new(Camera {
+            position: Vec3::new(0., 0., 5.),
+            orientation: Quaternion::identity(),
+            fov: 0.1,
+            mapping_function: MappingFunction::Linear,
+            aperture: 0.,
+            focus_distance: 5.,
+            aperture_blades: None,
+        });
+        scene.add_shape(Shape {
+            name: None,
+            material: Material::color(Vec3::one()),
+            kind: ShapeKind::Sphere(Sphere {
+                center: Vec3::zero(),
+                radius: 1.,
+            }),
+        });
+        scene.add_light(Light {
+            name: None,
+            intensity: 1.,
+            color: LightColor::default(),
+            kind: LightKind::Directional {
+                direction: -Vec3::unit_y(),
+                angular_diameter: 0.,
+            },
+        });
+        let options = RenderOptions {
+            width: 8,
+            height: 8,
+            max_ray_depth: 1,
+            ..Default::default()
+        };
+        let brightness =
+            |scene: &Scene| render(&options, scene).data.iter().copied().sum::<Vec3>().x;
+        assert!(brightness(&scene) > 0.);
+
+        // Far away, but still between the ball and the sun
+        scene.add_shape(Shape {
+            name: None,
+            material: Material::color(Vec3::one()),
+            kind: ShapeKind::BoundedPlane(BoundedPlane {
+                center: Vec3::new(0., 1e5, 0.),
+                a: Vec3::unit_x() * 1e3,
+                b: Vec3::unit_z() * 1e3,
+            }),
+        });
+        assert_eq!(brightness(&scene), 0.);
+    }
+
+    #[test]
+    fn test_progressive_first_pass_matches_render() {
+        let scene = presets::light_and_box();
+        let options = RenderOptions {
+            width: 16,
+            height: 16,
+            pixel_samples: 4,
+            filter: Filter::Tent,
+            seed: Some(3),
+            ..Default::default()
+        };
+        let mut renderer = ProgressiveRenderer::default();
+        renderer.pass(&options, &scene);
+        assert_eq!(renderer.samples(), 4);
+        assert_eq!(renderer.image().data, render(&options, &scene).data);
+
+        renderer.reset();
+        assert_eq!(renderer.samples(), 0);
+    }
+
+    #[test]
+    fn test_environment_light_without_randomness() {
+        let mut scene = presets::light_and_box();
+        scene.background = Background::Gradient {
+            bottom: Vec3::zero(),
+            top: Vec3::one(),
+        };
+        let options = RenderOptions {
+            width: 8,
+            height: 8,
+            use_randomness: false,
+            environment_samples: 3,
+            ..Default::default()
+        };
+        assert_eq!(render(&options, &scene).data, render(&options, &scene).data);
+    }
+
+    #[test]
+    fn test_tone_mapping_keeps_highlights() {
+        let displayed = |sky: f32, tone_mapping| {
+            let mut scene = presets::light_and_box();
+            scene.background = Background::Solid(Vec3::broadcast(sky));
+            let options = RenderOptions {
+                width: 4,
+                height: 4,
+                use_randomness: false,
+                display: DisplayTransform {
+                    tone_mapping,
+                    ..DisplayTransform::new()
+                },
+                ..Default::default()
+            };
+            // The top left corner looks at the sky
+            render(&options, &scene).get_raw_data(&options.display)[0]
+        };
+        assert_eq!(
+            displayed(2., ToneMapping::Clamp),
+            displayed(4., ToneMapping::Clamp)
+        );
+        assert!(displayed(2., ToneMapping::Reinhard) < displayed(4., ToneMapping::Reinhard));
+        assert!(displayed(2., ToneMapping::AcesFilmic) < displayed(4., ToneMapping::AcesFilmic));
+    }
+
+    #[test]
+    fn test_adaptive_sampling() {
+        let scene = presets::light_and_box();
+        let options = RenderOptions {
+            width: 16,
+            height: 16,
+            pixel_samples: 4,
+            adaptive: Some(AdaptiveSampling {
+                threshold: 0.02,
+                max_samples: 32,
+            }),
+            seed: Some(5),
+            ..Default::default()
+        };
+        let (image, map) = render_with_sample_map(&options, &scene);
+        assert_eq!(image.data, render(&options, &scene).data);
+        let fewest = map.data.iter().map(|c| c.x).fold(f32::INFINITY, f32::min);
+        let most = map.data.iter().map(|c| c.x).fold(0., f32::max);
+        // The empty sky is flat while the soft shadow is noisy
+        assert_eq!(fewest, 4. / 32.);
+        assert_eq!(most, 1.);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_tiles() {
+        // Neither side is a multiple of the tile size
+        let (width, height) = (37, 19);
+        let mut covered = vec![0; width * height];
+        for tile in tiles(width, height) {
+            assert!(tile.len() <= TILE_SIZE * TILE_SIZE);
+            for i in tile.pixels(width) {
+                covered[i] += 1;
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+
+        // Every tile is handed out once, whether taken from the shared queue
+        // or stolen from another thread
+        let injector = Injector::new();
+        for tile in tiles(width, height) {
+            injector.push(tile);
+        }
+        let workers = [Worker::new_fifo(), Worker::new_fifo()];
+        let stealers: Vec<_> = workers.iter().map(Worker::stealer).collect();
+        let mut handed_out = 0;
+        let first = next_tile(&workers[0], &injector, &stealers).unwrap();
+        handed_out += first.len();
+        while let Some(tile) = next_tile(&workers[1], &injector, &stealers) {
+            handed_out += tile.len();
+        }
+        assert!(next_tile(&workers[0], &injector, &stealers).is_none());
+        assert_eq!(handed_out, width * height);
+    }
+
+    #[test]
+    fn test_seeded_render_is_reproducible() {
+        let scene = presets::cornellbox();
+        let options = RenderOptions {
+            width: 24,
+            height: 24,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let image = render(&options, &scene);
+        // 0 threads renders on one
+        for threads in [0, 1, 5] {
+            let options = RenderOptions {
+                threads,
+                ..options.clone()
+            };
+            assert_eq!(render(&options, &scene).data, image.data);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    background::EnvironmentMap, obj, relative_to, render::RenderOptions, Background, Bvh, Camera,
+    Error, Light, Shape, Vec3,
+};
+
+/// Everything that is rendered: the camera, the shapes and the lights
+pub struct Scene {
+    pub camera: Camera,
+    pub background: Background,
+    shapes: Vec<Shape>,
+    lights: Vec<Light>,
+    /// `None` when the shapes have changed since it was built
+    bvh: Option<Bvh>,
+}
+
+impl Scene {
+    pub fn new(camera: Camera) -> Self {
+        Self {
+            camera,
+            background: Background::default(),
+            shapes: vec![],
+            lights: vec![],
+            bvh: None,
+        }
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_shape(mut self, shape: Shape) -> Self {
+        self.add_shape(shape);
+        self
+    }
+
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.add_light(light);
+        self
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
+    }
+
+    pub fn add_shape(&mut self, shape: Shape) {
+        self.shapes.push(shape);
+        self.bvh = None;
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    pub fn shapes(&self) -> &[Shape] {
+        &self.shapes
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// The first shape with the given name
+    pub fn shape(&self, name: &str) -> Option<&Shape> {
+        self.shapes.iter().find(|s| s.name.as_deref() == Some(name))
+    }
+
+    pub fn shape_mut(&mut self, name: &str) -> Option<&mut Shape> {
+        // The shape may be moved, so the hierarchy can't be trusted anymore
+        self.bvh = None;
+        self.shapes
+            .iter_mut()
+            .find(|s| s.name.as_deref() == Some(name))
+    }
+
+    pub fn remove_shape(&mut self, name: &str) -> Option<Shape> {
+        let i = self
+            .shapes
+            .iter()
+            .position(|s| s.name.as_deref() == Some(name))?;
+        self.bvh = None;
+        Some(self.shapes.remove(i))
+    }
+
+    /// The first light with the given name
+    pub fn light(&self, name: &str) -> Option<&Light> {
+        self.lights.iter().find(|l| l.name.as_deref() == Some(name))
+    }
+
+    pub fn light_mut(&mut self, name: &str) -> Option<&mut Light> {
+        self.lights
+            .iter_mut()
+            .find(|l| l.name.as_deref() == Some(name))
+    }
+
+    pub fn remove_light(&mut self, name: &str) -> Option<Light> {
+        let i = self
+            .lights
+            .iter()
+            .position(|l| l.name.as_deref() == Some(name))?;
+        Some(self.lights.remove(i))
+    }
+
+    /// Builds the bounding volume hierarchy over the shapes, so that it can be
+    /// reused for every render until the shapes change
+    pub fn build_bvh(&mut self) {
+        if self.bvh.is_none() {
+            self.bvh = Some(Bvh::new(&self.shapes));
+        }
+    }
+
+    /// The hierarchy from `build_bvh`, or a newly built one if the shapes have
+    /// changed since then
+    pub fn bvh(&self) -> Cow<Bvh> {
+        match &self.bvh {
+            Some(bvh) => Cow::Borrowed(bvh),
+            None => Cow::Owned(Bvh::new(&self.shapes)),
+        }
+    }
+
+    /// Loads a scene file, including any models it refers to
+    pub fn load(path: impl AsRef<Path>) -> Result<(Scene, RenderOptions), Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| Error::file(path, err))?;
+        let file: SceneFile = ron::from_str(&text).map_err(|err| {
+            Error::parse(
+                path,
+                err.position.line,
+                format!("{} (column {})", err.code, err.position.col),
+            )
+        })?;
+
+        let background = match file.background {
+            BackgroundFile::Solid(color) => Background::Solid(color),
+            BackgroundFile::Gradient { bottom, top } => Background::Gradient { bottom, top },
+            BackgroundFile::EnvironmentMap {
+                path: map_path,
+                strength,
+                rotation,
+            } => {
+                let mut map = EnvironmentMap::load(relative_to(path, map_path))?;
+                map.strength = strength;
+                map.rotation = rotation;
+                Background::EnvironmentMap(map)
+            }
+        };
+        let mut scene = Scene::new(file.camera).with_background(background);
+        for mut shape in file.shapes {
+            shape.material.load_textures(path)?;
+            scene.add_shape(shape);
+        }
+        for model in file.models {
+            for shape in obj::load(relative_to(path, model))? {
+                scene.add_shape(shape);
+            }
+        }
+        for light in file.lights {
+            scene.add_light(light);
+        }
+        scene.build_bvh();
+
+        Ok((scene, file.options))
+    }
+}
+
+/// A scene as described by a RON scene file
+#[derive(Deserialize)]
+struct SceneFile {
+    camera: Camera,
+    #[serde(default)]
+    background: BackgroundFile,
+    #[serde(default)]
+    shapes: Vec<Shape>,
+    #[serde(default)]
+    lights: Vec<Light>,
+    /// OBJ files whose shapes are added to the scene, relative to the scene file
+    #[serde(default)]
+    models: Vec<PathBuf>,
+    #[serde(default)]
+    options: RenderOptions,
+}
+
+/// A `Background` in a scene file, where environment maps are referred to by
+/// their path relative to the scene file
+#[derive(Deserialize)]
+enum BackgroundFile {
+    Solid(Vec3),
+    Gradient {
+        bottom: Vec3,
+        top: Vec3,
+    },
+    EnvironmentMap {
+        path: PathBuf,
+        #[serde(default = "default_strength")]
+        strength: f32,
+        #[serde(default)]
+        rotation: f32,
+    },
+}
+
+impl Default for BackgroundFile {
+    fn default() -> Self {
+        Self::Solid(Vec3::zero())
+    }
+}
+
+fn default_strength() -> f32 {
+    1.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    #[test]
+    fn test_missing_file_error_names_it() {
+        for err in [
+            Scene::load("missing/scene.ron").map(drop).unwrap_err(),
+            obj::load("missing/model.obj").map(drop).unwrap_err(),
+            crate::Image::load("missing/texture.png")
+                .map(drop)
+                .unwrap_err(),
+        ] {
+            assert!(err.to_string().starts_with("missing/"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_load_preset_scene() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../scenes/cornellbox.ron");
+        let (scene, _) = Scene::load(path).unwrap();
+        let preset = presets::cornellbox();
+        assert_eq!(scene.shapes(), preset.shapes());
+        assert_eq!(scene.lights().len(), preset.lights().len());
+        assert!(scene.shape("glass ball").is_some());
+
+        match Scene::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")) {
+            Err(Error::ParseError { line, .. }) => assert_eq!(line, 1),
+            _ => panic!("expected a parse error"),
+        }
+    }
+}
@@ -1,27 +1,45 @@
-use std::f32::consts::FRAC_PI_2;
+use std::{
+    f32::consts::{FRAC_PI_2, PI, TAU},
+    sync::Arc,
+};
 
-use crate::{material::refractive_indices, orthogonal, Material, Quaternion, Ray, Vec3};
+use rand::Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bvh::{Aabb, Bounded, Bvh},
+    material::refractive_indices,
+    orthogonal, Material, Quaternion, Ray, Vec2, Vec3,
+};
 
 #[derive(Debug)]
 pub struct Intersection {
     pub ray: Ray,
     pub dist: f32,
     pub point: Vec3,
+    /// The normal used for shading, which may be interpolated or perturbed by
+    /// normal maps
     pub normal: Vec3,
+    /// The actual normal of the surface, which is what `ignore_normal` is
+    /// compared with
+    pub geometric_normal: Vec3,
+    /// Texture coordinates of the point
+    pub uv: Vec2,
+    /// The direction in which `uv.x` increases along the surface. The
+    /// direction in which `uv.y` increases is about `normal × tangent`.
+    pub tangent: Vec3,
 }
 
 impl Intersection {
-    pub fn reflection(&self, roughness: f32, use_randomness: bool) -> Ray {
-        let get_random = || {
-            use rand::prelude::*;
-            use rand_distr::Normal;
-            if roughness == 0. || !use_randomness {
-                0.
-            } else {
-                rand::thread_rng()
-                    .sample::<f32, _>(Normal::new(0., roughness / 3.).unwrap())
-                    .clamp(-1., 1.)
-            }
+    /// Reflects the ray, with the normal randomly perturbed based on the
+    /// roughness if `rng` is `Some`
+    pub fn reflection(&self, roughness: f32, mut rng: Option<&mut impl Rng>) -> Ray {
+        let mut get_random = || match &mut rng {
+            Some(rng) if roughness != 0. => rng
+                .sample::<f32, _>(Normal::new(0., roughness / 3.).unwrap())
+                .clamp(-1., 1.),
+            _ => 0.,
         };
 
         let (a, b) = orthogonal(self.normal);
@@ -35,6 +53,25 @@ impl Intersection {
             Quaternion::rotation_3d(180f32.to_radians(), normal) * -self.ray.direction,
         )
     }
+    /// The fraction of light that is reflected rather than refracted, from the
+    /// Fresnel equations for unpolarized light. It is 1 past the critical
+    /// angle, where all of the light is reflected.
+    pub fn reflectance(&self, refractive_index: f32) -> f32 {
+        let cos_i = self.ray.direction.dot(self.normal);
+        let (n_i, n_t, cos_i) = if cos_i < 0. {
+            (refractive_indices::AIR, refractive_index, -cos_i)
+        } else {
+            (refractive_index, refractive_indices::AIR, cos_i)
+        };
+        let sin_t = n_i / n_t * (1. - cos_i * cos_i).max(0.).sqrt();
+        if sin_t >= 1. {
+            return 1.;
+        }
+        let cos_t = (1. - sin_t * sin_t).sqrt();
+        let s = (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t);
+        let p = (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t);
+        (s * s + p * p) / 2.
+    }
     pub fn refraction(&self, refractive_index: f32) -> Ray {
         let side = self.ray.direction.cross(self.normal);
 
@@ -48,8 +85,6 @@ impl Intersection {
         let theta1 = self.ray.direction.angle_between(forwards);
         let theta2 = (refractive_index_change * theta1.sin()).asin();
 
-        // NOTE: the 'critical angle' is ignored here
-
         if theta2.is_nan() {
             // Total internal refraction
             Ray::new(
@@ -62,17 +97,22 @@ impl Intersection {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Shape {
+    /// Used to look the shape up in a `Scene`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     pub material: Material,
     pub kind: ShapeKind,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum ShapeKind {
     Plane(Plane),
     BoundedPlane(BoundedPlane),
     Sphere(Sphere),
+    Triangle(Triangle),
+    Mesh(Mesh),
 }
 
 pub trait Intersect {
@@ -95,12 +135,83 @@ impl Intersect for ShapeKind {
             ShapeKind::Plane(p) => p.intersection(ray, ignore_normal),
             ShapeKind::BoundedPlane(p) => p.intersection(ray, ignore_normal),
             ShapeKind::Sphere(s) => s.intersection(ray, ignore_normal),
+            ShapeKind::Triangle(t) => t.intersection(ray, ignore_normal),
+            ShapeKind::Mesh(m) => m.intersection(ray, ignore_normal),
+        }
+    }
+}
+
+impl ShapeKind {
+    /// The area of the surface, which is infinite for planes
+    pub fn area(&self) -> f32 {
+        match self {
+            ShapeKind::Plane(_) => f32::INFINITY,
+            ShapeKind::BoundedPlane(p) => p.a.cross(p.b).magnitude() * 4.,
+            ShapeKind::Sphere(s) => 2. * TAU * s.radius * s.radius,
+            ShapeKind::Triangle(t) => t.area(),
+            ShapeKind::Mesh(m) => m.area_sums.last().copied().unwrap_or(0.),
+        }
+    }
+
+    /// A point on the surface and the normal there, spread evenly over the
+    /// surface as `uv` covers 0..1 in both directions. Planes have no such
+    /// points as they are infinite.
+    pub fn sample_surface(&self, uv: Vec2) -> Option<(Vec3, Vec3)> {
+        match self {
+            ShapeKind::Plane(_) => None,
+            ShapeKind::BoundedPlane(p) => Some((
+                p.center + p.a * (uv.x * 2. - 1.) + p.b * (uv.y * 2. - 1.),
+                p.normal(),
+            )),
+            ShapeKind::Sphere(s) => {
+                let y = 1. - 2. * uv.y;
+                let r = (1. - y * y).max(0.).sqrt();
+                let angle = uv.x * TAU;
+                let normal = Vec3::new(r * angle.cos(), y, r * angle.sin());
+                Some((s.center + normal * s.radius, normal))
+            }
+            ShapeKind::Triangle(t) => Some(t.sample_surface(uv)),
+            ShapeKind::Mesh(m) => {
+                // Pick a triangle with a probability proportional to its area,
+                // and reuse what's left of `uv.x` to place the point on it
+                let target = uv.x * m.area_sums.last()?;
+                let i = m
+                    .area_sums
+                    .partition_point(|&sum| sum < target)
+                    .min(m.area_sums.len() - 1);
+                let start = if i == 0 { 0. } else { m.area_sums[i - 1] };
+                let area = m.area_sums[i] - start;
+                let u = if area > 0. {
+                    ((target - start) / area).clamp(0., 1.)
+                } else {
+                    0.
+                };
+                Some(m.triangle(i).sample_surface(Vec2::new(u, uv.y)))
+            }
+        }
+    }
+}
+
+impl Bounded for Shape {
+    fn bounds(&self) -> Option<Aabb> {
+        self.kind.bounds()
+    }
+}
+
+impl Bounded for ShapeKind {
+    fn bounds(&self) -> Option<Aabb> {
+        match self {
+            ShapeKind::Plane(_) => None,
+            ShapeKind::BoundedPlane(p) => p.bounds(),
+            ShapeKind::Sphere(s) => s.bounds(),
+            ShapeKind::Triangle(t) => t.bounds(),
+            ShapeKind::Mesh(m) => m.bounds(),
         }
     }
 }
 
 /// An infinite plane
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Plane {
     pub center: Vec3,
     pub normal: Vec3,
@@ -113,11 +224,18 @@ impl Intersect for Plane {
         }
         let dist = (self.center - ray.origin).dot(self.normal) / ray.direction.dot(self.normal);
         if 0. < dist {
+            let point = ray.origin + ray.direction * dist;
+            // One unit of texture space per unit of distance along the plane
+            let (a, b) = orthogonal(self.normal);
+            let center2point = point - self.center;
             Some(Intersection {
                 ray,
                 dist,
-                point: ray.origin + ray.direction * dist,
+                point,
                 normal: self.normal,
+                geometric_normal: self.normal,
+                uv: Vec2::new(center2point.dot(a), center2point.dot(b)),
+                tangent: a,
             })
         } else {
             None
@@ -125,7 +243,7 @@ impl Intersect for Plane {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct BoundedPlane {
     pub center: Vec3,
     pub a: Vec3,
@@ -133,7 +251,8 @@ pub struct BoundedPlane {
 }
 
 impl BoundedPlane {
-    fn normal(&self) -> Vec3 {
+    /// The side that `a × b` points to
+    pub fn normal(&self) -> Vec3 {
         self.a.cross(self.b).normalized()
     }
 }
@@ -155,11 +274,19 @@ impl Intersect for BoundedPlane {
             && c2p_proj_on_a_hat.magnitude_squared() <= self.a.magnitude_squared()
             && c2p_proj_on_b_hat.magnitude_squared() <= self.b.magnitude_squared()
         {
+            // 0..1 from `center - a` to `center + a`, and likewise for `b`
+            let uv = Vec2::new(
+                center2point.dot(a_hat) / self.a.magnitude(),
+                center2point.dot(b_hat) / self.b.magnitude(),
+            );
             Some(Intersection {
                 ray,
                 dist,
                 point,
                 normal,
+                geometric_normal: normal,
+                uv: (uv + Vec2::one()) / 2.,
+                tangent: a_hat,
             })
         } else {
             None
@@ -167,7 +294,18 @@ impl Intersect for BoundedPlane {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+impl Bounded for BoundedPlane {
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::from_points([
+            self.center + self.a + self.b,
+            self.center + self.a - self.b,
+            self.center - self.a + self.b,
+            self.center - self.a - self.b,
+        ]))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
@@ -178,18 +316,465 @@ impl Intersect for Sphere {
         let a = ray.direction.dot(ray.origin - self.center).powi(2)
             - (ray.origin - self.center).magnitude_squared()
             + self.radius.powi(2);
-        let dist = -ray.direction.dot(ray.origin - self.center) - a.sqrt();
-        let point = ray.origin + dist * ray.direction;
-        let normal = (point - self.center).normalized();
-        if dist >= 0. && !ignore_normal.map_or(false, |n| (n - normal).is_approx_zero()) {
-            Some(Intersection {
-                ray,
-                dist,
-                point,
-                normal,
+        let middle = -ray.direction.dot(ray.origin - self.center);
+        // The far side is hit by rays that start inside the sphere, such as
+        // ones refracted into it
+        [middle - a.sqrt(), middle + a.sqrt()]
+            .into_iter()
+            .find_map(|dist| {
+                let point = ray.origin + dist * ray.direction;
+                let normal = (point - self.center).normalized();
+                (dist >= 0. && !ignore_normal.map_or(false, |n| (n - normal).is_approx_zero()))
+                    .then(|| Intersection {
+                        ray,
+                        dist,
+                        point,
+                        normal,
+                        geometric_normal: normal,
+                        uv: Sphere::uv(normal),
+                        tangent: Sphere::tangent(normal),
+                    })
             })
+    }
+}
+
+impl Sphere {
+    /// Longitude and latitude for the point on the sphere with the normal,
+    /// with the seam in the -z direction
+    fn uv(normal: Vec3) -> Vec2 {
+        Vec2::new(
+            normal.x.atan2(normal.z) / TAU + 0.5,
+            1. - normal.y.clamp(-1., 1.).acos() / PI,
+        )
+    }
+
+    /// The eastward direction, along which the longitude increases
+    fn tangent(normal: Vec3) -> Vec3 {
+        let east = Vec3::new(normal.z, 0., -normal.x);
+        if east.is_approx_zero() {
+            // At the poles every direction is east
+            orthogonal(normal).0
+        } else {
+            east.normalized()
+        }
+    }
+}
+
+impl Bounded for Sphere {
+    fn bounds(&self) -> Option<Aabb> {
+        let r = Vec3::broadcast(self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+}
+
+/// Intersections closer than this are ignored for triangles, since their
+/// normals can't be relied on to recognize the surface a ray was sent from
+const TRIANGLE_EPSILON: f32 = 1e-4;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+}
+
+impl Triangle {
+    fn normal(&self) -> Vec3 {
+        (self.b - self.a).cross(self.c - self.a).normalized()
+    }
+
+    fn area(&self) -> f32 {
+        (self.b - self.a).cross(self.c - self.a).magnitude() / 2.
+    }
+
+    fn sample_surface(&self, uv: Vec2) -> (Vec3, Vec3) {
+        let s = uv.x.sqrt();
+        let point = self.a * (1. - s) + self.b * (s * (1. - uv.y)) + self.c * (s * uv.y);
+        (point, self.normal())
+    }
+
+    /// Möller–Trumbore intersection. Returns the distance along the ray and
+    /// the barycentric coordinates of `b` and `c` at the intersection.
+    fn barycentric_intersection(&self, ray: Ray) -> Option<(f32, f32, f32)> {
+        let ab = self.b - self.a;
+        let ac = self.c - self.a;
+        let p = ray.direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < f32::EPSILON {
+            // The ray is parallel to the triangle
+            return None;
+        }
+        let a2origin = ray.origin - self.a;
+        let u = a2origin.dot(p) / det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = a2origin.cross(ab);
+        let v = ray.direction.dot(q) / det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let dist = ac.dot(q) / det;
+        if dist > TRIANGLE_EPSILON {
+            Some((dist, u, v))
         } else {
             None
         }
     }
 }
+
+impl Intersect for Triangle {
+    fn intersection(&self, ray: Ray, ignore_normal: Option<Vec3>) -> Option<Intersection> {
+        let normal = self.normal();
+        if ignore_normal.map_or(false, |n| (n - normal).is_approx_zero()) {
+            return None;
+        }
+        let (dist, u, v) = self.barycentric_intersection(ray)?;
+        Some(Intersection {
+            ray,
+            dist,
+            point: ray.origin + ray.direction * dist,
+            normal,
+            geometric_normal: normal,
+            uv: Vec2::new(u, v),
+            tangent: (self.b - self.a).normalized(),
+        })
+    }
+}
+
+impl Bounded for Triangle {
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::from_points([self.a, self.b, self.c]))
+    }
+}
+
+/// A triangle mesh. The buffers are reference counted so that several meshes
+/// (e.g. one per material) can share them.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(try_from = "MeshBuffers", into = "MeshBuffers")]
+pub struct Mesh {
+    vertices: Arc<Vec<Vec3>>,
+    normals: Option<Arc<Vec<Vec3>>>,
+    uvs: Option<Arc<Vec<Vec2>>>,
+    indices: Arc<Vec<[usize; 3]>>,
+    bvh: Bvh,
+    /// The total area of the triangles up to and including each one
+    area_sums: Vec<f32>,
+}
+
+impl Mesh {
+    /// Creates a mesh with a triangle for every entry in `indices`. If
+    /// `normals` is `Some`, it must contain one normal per vertex, and they
+    /// will be interpolated across the triangles for smooth shading. The same
+    /// goes for the texture coordinates in `uvs`; without them the barycentric
+    /// coordinates of every triangle are used. Fails if that isn't the case or
+    /// an index is out of bounds.
+    pub fn new(
+        vertices: impl Into<Arc<Vec<Vec3>>>,
+        normals: Option<Arc<Vec<Vec3>>>,
+        uvs: Option<Arc<Vec<Vec2>>>,
+        indices: impl Into<Arc<Vec<[usize; 3]>>>,
+    ) -> Result<Self, String> {
+        let vertices = vertices.into();
+        let indices = indices.into();
+        if let Some(normals) = &normals {
+            if normals.len() != vertices.len() {
+                return Err(format!(
+                    "expected one normal per vertex ({}), found {}",
+                    vertices.len(),
+                    normals.len()
+                ));
+            }
+        }
+        if let Some(uvs) = &uvs {
+            if uvs.len() != vertices.len() {
+                return Err(format!(
+                    "expected one texture coordinate per vertex ({}), found {}",
+                    vertices.len(),
+                    uvs.len()
+                ));
+            }
+        }
+        if let Some(i) = indices.iter().flatten().find(|&&i| i >= vertices.len()) {
+            return Err(format!("vertex index {} is out of bounds", i));
+        }
+        let triangles: Vec<_> = indices
+            .iter()
+            .map(|&[a, b, c]| Triangle {
+                a: vertices[a],
+                b: vertices[b],
+                c: vertices[c],
+            })
+            .collect();
+        let bvh = Bvh::new(&triangles);
+        let area_sums = triangles
+            .iter()
+            .scan(0., |sum, triangle| {
+                *sum += triangle.area();
+                Some(*sum)
+            })
+            .collect();
+        Ok(Self {
+            vertices,
+            normals,
+            uvs,
+            indices,
+            bvh,
+            area_sums,
+        })
+    }
+
+    pub fn vertices(&self) -> &Arc<Vec<Vec3>> {
+        &self.vertices
+    }
+
+    pub fn normals(&self) -> Option<&Arc<Vec<Vec3>>> {
+        self.normals.as_ref()
+    }
+
+    pub fn uvs(&self) -> Option<&Arc<Vec<Vec2>>> {
+        self.uvs.as_ref()
+    }
+
+    pub fn indices(&self) -> &Arc<Vec<[usize; 3]>> {
+        &self.indices
+    }
+
+    pub fn triangle(&self, i: usize) -> Triangle {
+        let [a, b, c] = self.indices[i];
+        Triangle {
+            a: self.vertices[a],
+            b: self.vertices[b],
+            c: self.vertices[c],
+        }
+    }
+
+    fn triangle_intersection(
+        &self,
+        i: usize,
+        ray: Ray,
+        ignore_normal: Option<Vec3>,
+    ) -> Option<Intersection> {
+        let triangle = self.triangle(i);
+        let geometric_normal = triangle.normal();
+        if ignore_normal.map_or(false, |n| (n - geometric_normal).is_approx_zero()) {
+            return None;
+        }
+        let (dist, u, v) = triangle.barycentric_intersection(ray)?;
+        let [a, b, c] = self.indices[i];
+        let normal = match &self.normals {
+            Some(normals) => {
+                (normals[a] * (1. - u - v) + normals[b] * u + normals[c] * v).normalized()
+            }
+            None => geometric_normal,
+        };
+        let (uv, tangent) = match &self.uvs {
+            Some(uvs) => (
+                uvs[a] * (1. - u - v) + uvs[b] * u + uvs[c] * v,
+                uv_tangent(&triangle, [uvs[a], uvs[b], uvs[c]]),
+            ),
+            None => (Vec2::new(u, v), (triangle.b - triangle.a).normalized()),
+        };
+        Some(Intersection {
+            ray,
+            dist,
+            point: ray.origin + ray.direction * dist,
+            normal,
+            geometric_normal,
+            uv,
+            tangent,
+        })
+    }
+}
+
+/// The direction in which the u texture coordinate increases across the
+/// triangle
+fn uv_tangent(triangle: &Triangle, [uv_a, uv_b, uv_c]: [Vec2; 3]) -> Vec3 {
+    let (edge1, edge2) = (triangle.b - triangle.a, triangle.c - triangle.a);
+    let (duv1, duv2) = (uv_b - uv_a, uv_c - uv_a);
+    let det = duv1.x * duv2.y - duv2.x * duv1.y;
+    if det.abs() < f32::EPSILON {
+        // The texture coordinates don't span an area
+        return edge1.normalized();
+    }
+    ((edge1 * duv2.y - edge2 * duv1.y) / det).normalized()
+}
+
+impl Intersect for Mesh {
+    fn intersection(&self, ray: Ray, ignore_normal: Option<Vec3>) -> Option<Intersection> {
+        self.bvh
+            .closest(ray, |i| self.triangle_intersection(i, ray, ignore_normal))
+            .map(|(_, intersection)| intersection)
+    }
+}
+
+impl Bounded for Mesh {
+    fn bounds(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+}
+
+/// The serialized form of a `Mesh`
+#[derive(Serialize, Deserialize)]
+struct MeshBuffers {
+    vertices: Vec<Vec3>,
+    #[serde(default)]
+    normals: Option<Vec<Vec3>>,
+    #[serde(default)]
+    uvs: Option<Vec<Vec2>>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl TryFrom<MeshBuffers> for Mesh {
+    type Error = String;
+
+    fn try_from(buffers: MeshBuffers) -> Result<Self, Self::Error> {
+        Mesh::new(
+            buffers.vertices,
+            buffers.normals.map(Arc::new),
+            buffers.uvs.map(Arc::new),
+            buffers.indices,
+        )
+    }
+}
+
+impl From<Mesh> for MeshBuffers {
+    fn from(mesh: Mesh) -> Self {
+        Self {
+            vertices: mesh.vertices.to_vec(),
+            normals: mesh.normals.map(|normals| normals.to_vec()),
+            uvs: mesh.uvs.map(|uvs| uvs.to_vec()),
+            indices: mesh.indices.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triangle() {
+        let triangle = Triangle {
+            a: Vec3::zero(),
+            b: Vec3::unit_x(),
+            c: Vec3::unit_y(),
+        };
+        let down = -Vec3::unit_z();
+        let hit = triangle
+            .intersection(Ray::new(Vec3::new(0.25, 0.25, 2.), down), None)
+            .unwrap();
+        assert_eq!(hit.dist, 2.);
+        assert_eq!(hit.point, Vec3::new(0.25, 0.25, 0.));
+        assert_eq!(hit.normal, Vec3::unit_z());
+        assert_eq!(hit.uv, Vec2::new(0.25, 0.25));
+        // Outside of the hypotenuse
+        assert!(triangle
+            .intersection(Ray::new(Vec3::new(0.6, 0.6, 2.), down), None)
+            .is_none());
+        // Parallel to the triangle, through it
+        assert!(triangle
+            .intersection(Ray::new(Vec3::new(-1., 0.25, 0.), Vec3::unit_x()), None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_mesh() {
+        let normals = vec![
+            Vec3::unit_z(),
+            Vec3::new(1., 0., 1.).normalized(),
+            Vec3::new(0., 1., 1.).normalized(),
+        ];
+        let uvs = vec![Vec2::zero(), Vec2::new(2., 0.), Vec2::new(0., 4.)];
+        let mesh = Mesh::new(
+            vec![Vec3::zero(), Vec3::unit_x(), Vec3::unit_y()],
+            Some(Arc::new(normals.clone())),
+            Some(Arc::new(uvs)),
+            vec![[0, 1, 2]],
+        )
+        .unwrap();
+        let hit = mesh
+            .intersection(Ray::new(Vec3::new(0.25, 0.5, 1.), -Vec3::unit_z()), None)
+            .unwrap();
+        let normal = (normals[0] * 0.25 + normals[1] * 0.25 + normals[2] * 0.5).normalized();
+        assert!((hit.normal - normal).magnitude() < 1e-6);
+        assert_eq!(hit.geometric_normal, Vec3::unit_z());
+        assert_eq!(hit.uv, Vec2::new(0.5, 2.));
+    }
+
+    #[test]
+    fn test_mesh_buffers() {
+        let buffers = |normals, uvs, indices| MeshBuffers {
+            vertices: vec![Vec3::zero(), Vec3::unit_x(), Vec3::unit_y()],
+            normals,
+            uvs,
+            indices,
+        };
+        assert!(Mesh::try_from(buffers(None, None, vec![[0, 1, 2]])).is_ok());
+        assert_eq!(
+            Mesh::try_from(buffers(None, None, vec![[0, 1, 3]])).unwrap_err(),
+            "vertex index 3 is out of bounds"
+        );
+        assert!(
+            Mesh::try_from(buffers(Some(vec![Vec3::unit_z()]), None, vec![[0, 1, 2]]))
+                .unwrap_err()
+                .contains("normal")
+        );
+        assert!(
+            Mesh::try_from(buffers(None, Some(vec![Vec2::zero(); 4]), vec![[0, 1, 2]]))
+                .unwrap_err()
+                .contains("texture coordinate")
+        );
+    }
+
+    #[test]
+    fn test_sample_surface() {
+        let kinds = [
+            ShapeKind::BoundedPlane(BoundedPlane {
+                center: Vec3::new(0., 2., 0.),
+                a: Vec3::new(1., 0., 0.),
+                b: Vec3::new(0., 0., 2.),
+            }),
+            ShapeKind::Sphere(Sphere {
+                center: Vec3::new(1., 0., 0.),
+                radius: 2.,
+            }),
+            ShapeKind::Mesh(
+                Mesh::new(
+                    vec![
+                        Vec3::zero(),
+                        Vec3::unit_x(),
+                        Vec3::unit_y(),
+                        Vec3::new(3., 0., 1.),
+                    ],
+                    None,
+                    None,
+                    vec![[0, 1, 2], [1, 3, 2]],
+                )
+                .unwrap(),
+            ),
+        ];
+        let areas = [8., 16. * PI, 0.5 + 6f32.sqrt() / 2.];
+        for (kind, area) in kinds.iter().zip(areas) {
+            assert!((kind.area() - area).abs() < 1e-4, "{:?}", kind);
+            for i in 0..10 {
+                for j in 0..10 {
+                    let uv = Vec2::new(i as f32 + 0.5, j as f32 + 0.5) / 10.;
+                    let (point, normal) = kind.sample_surface(uv).unwrap();
+                    // Looking at the point along the normal finds it
+                    let origin = point + normal * 10.;
+                    let hit = kind
+                        .intersection(Ray::new(origin, -normal), None)
+                        .unwrap_or_else(|| panic!("{:?} {}", kind, uv));
+                    assert!((hit.point - point).magnitude() < 1e-3);
+                }
+            }
+        }
+        assert!(ShapeKind::Plane(Plane {
+            center: Vec3::zero(),
+            normal: Vec3::unit_y(),
+        })
+        .sample_surface(Vec2::zero())
+        .is_none());
+    }
+}
@@ -0,0 +1,411 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use vek::Lerp;
+
+use crate::{relative_to, shapes::Intersection, Error, Image, Vec2, Vec3};
+
+/// Something that varies over a surface, such as its color. The procedural
+/// textures blend between the colors `a` and `b`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Texture {
+    Image(ImageTexture),
+    /// Cubes of side `size` alternating between `a` and `b`. In world space,
+    /// surfaces exactly on the border between two cubes will be noisy.
+    Checker {
+        a: Vec3,
+        b: Vec3,
+        size: f32,
+        #[serde(default)]
+        space: TextureSpace,
+    },
+    /// Perlin noise with `octaves` layers of finer and finer detail
+    Noise {
+        a: Vec3,
+        b: Vec3,
+        scale: f32,
+        octaves: usize,
+        #[serde(default)]
+        space: TextureSpace,
+    },
+    /// Stripes along the x axis, distorted by noise
+    Marble {
+        a: Vec3,
+        b: Vec3,
+        scale: f32,
+        turbulence: f32,
+        #[serde(default)]
+        space: TextureSpace,
+    },
+    /// Rings around the y axis, distorted by noise
+    Wood {
+        a: Vec3,
+        b: Vec3,
+        scale: f32,
+        turbulence: f32,
+        #[serde(default)]
+        space: TextureSpace,
+    },
+    /// `a` at `from`, blending to `b` at `to`
+    Gradient {
+        a: Vec3,
+        b: Vec3,
+        from: Vec3,
+        to: Vec3,
+        #[serde(default)]
+        space: TextureSpace,
+    },
+}
+
+/// The coordinates that procedural textures are evaluated at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextureSpace {
+    /// The texture coordinates, as (u, v, 0)
+    Uv,
+    /// The intersection point, so that the texture is carved out of a solid
+    /// block rather than wrapped around the surface
+    World,
+}
+
+impl TextureSpace {
+    fn coordinates(self, uv: Vec2, point: Vec3) -> Vec3 {
+        match self {
+            Self::Uv => Vec3::from(uv),
+            Self::World => point,
+        }
+    }
+}
+
+impl Default for TextureSpace {
+    fn default() -> Self {
+        Self::Uv
+    }
+}
+
+/// Octaves of turbulence used by marble and wood
+const TURBULENCE_OCTAVES: usize = 5;
+
+impl Texture {
+    /// The value at the intersected point
+    pub fn sample(&self, intersection: &Intersection) -> Vec3 {
+        self.sample_at(intersection.uv, intersection.point)
+    }
+
+    /// The value at the texture coordinates `uv`, or at `point` for textures
+    /// in world space
+    pub fn sample_at(&self, uv: Vec2, point: Vec3) -> Vec3 {
+        let (a, b, t) = match *self {
+            Self::Image(ref texture) => return texture.sample(uv),
+            Self::Checker { a, b, size, space } => {
+                let cell = (space.coordinates(uv, point) / size).map(f32::floor);
+                let parity = (cell.x + cell.y + cell.z).rem_euclid(2.);
+                (a, b, parity)
+            }
+            Self::Noise {
+                a,
+                b,
+                scale,
+                octaves,
+                space,
+            } => {
+                let p = space.coordinates(uv, point) * scale;
+                (a, b, 0.5 + 0.5 * fractal_noise(p, octaves))
+            }
+            Self::Marble {
+                a,
+                b,
+                scale,
+                turbulence,
+                space,
+            } => {
+                let p = space.coordinates(uv, point) * scale;
+                let phase = p.x + turbulence * fractal_turbulence(p, TURBULENCE_OCTAVES);
+                (a, b, 0.5 + 0.5 * phase.sin())
+            }
+            Self::Wood {
+                a,
+                b,
+                scale,
+                turbulence,
+                space,
+            } => {
+                let p = space.coordinates(uv, point) * scale;
+                let radius = Vec2::new(p.x, p.z).magnitude();
+                let ring = radius + turbulence * fractal_turbulence(p, TURBULENCE_OCTAVES);
+                (a, b, ring.rem_euclid(1.))
+            }
+            Self::Gradient {
+                a,
+                b,
+                from,
+                to,
+                space,
+            } => {
+                let along = to - from;
+                let p = space.coordinates(uv, point) - from;
+                (a, b, p.dot(along) / along.magnitude_squared())
+            }
+        };
+        Lerp::lerp(a, b, t.clamp(0., 1.))
+    }
+
+    /// Loads images that haven't been loaded yet, which is the case after
+    /// deserializing. Their paths are relative to `file`, and they are decoded
+    /// from sRGB if `srgb` is set and the texture doesn't say otherwise.
+    pub fn load_images(&mut self, file: &Path, srgb: bool) -> Result<(), Error> {
+        match self {
+            Self::Image(texture) if texture.image.data.is_empty() => {
+                let srgb = texture.srgb.unwrap_or(srgb);
+                *texture = ImageTexture::load(relative_to(file, &texture.path), srgb)?
+                    .with_wrap(texture.wrap)
+                    .with_scale(texture.scale);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What happens outside of the 0..1 texture coordinate range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WrapMode {
+    Repeat,
+    /// Repeat, but flipped every other time so that there are no seams
+    Mirror,
+    /// The edge pixels continue forever
+    Clamp,
+}
+
+impl WrapMode {
+    fn apply(self, i: isize, len: usize) -> usize {
+        let len = len as isize;
+        let i = match self {
+            Self::Repeat => i.rem_euclid(len),
+            Self::Mirror => {
+                let i = i.rem_euclid(2 * len);
+                if i < len {
+                    i
+                } else {
+                    2 * len - 1 - i
+                }
+            }
+            Self::Clamp => i.clamp(0, len - 1),
+        };
+        i as usize
+    }
+}
+
+impl Default for WrapMode {
+    fn default() -> Self {
+        Self::Repeat
+    }
+}
+
+/// An image stretched over texture coordinates 0..1, with (0, 0) in the
+/// bottom left corner. It is sampled with bilinear filtering.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageTexture {
+    /// Where the image was loaded from, or an empty path if it wasn't
+    pub path: PathBuf,
+    #[serde(skip, default = "empty_image")]
+    image: Arc<Image>,
+    /// Whether the image was converted from sRGB to linear colors when loaded.
+    /// If it isn't set in a scene file, only color maps are converted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub srgb: Option<bool>,
+    #[serde(default)]
+    pub wrap: WrapMode,
+    /// How many times the image is repeated per unit of texture coordinates
+    #[serde(default = "Vec2::one")]
+    pub scale: Vec2,
+}
+
+fn empty_image() -> Arc<Image> {
+    Arc::new(Image::new(vec![], 0, 0))
+}
+
+impl ImageTexture {
+    pub fn new(image: impl Into<Arc<Image>>) -> Self {
+        Self {
+            path: PathBuf::new(),
+            image: image.into(),
+            srgb: Some(false),
+            wrap: WrapMode::default(),
+            scale: Vec2::one(),
+        }
+    }
+
+    /// Loads an image file. Set `srgb` for images of colors, which are usually
+    /// sRGB encoded, but not for images of e.g. roughness or HDR images.
+    pub fn load(path: impl AsRef<Path>, srgb: bool) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut image = Image::load(path)?;
+        if srgb {
+            for pixel in &mut image.data {
+                *pixel = pixel.map(srgb_to_linear);
+            }
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            srgb: Some(srgb),
+            ..Self::new(image)
+        })
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec2) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let Image {
+            data,
+            width,
+            height,
+        } = &*self.image;
+        let uv = uv * self.scale;
+        // There is no pixel to pick for coordinates at infinity, e.g. from a
+        // degenerate triangle
+        if data.is_empty() || !uv.x.is_finite() || !uv.y.is_finite() {
+            return Vec3::one();
+        }
+        // Pixel centers are at half coordinates
+        let x = uv.x * *width as f32 - 0.5;
+        let y = (1. - uv.y) * *height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let pixel = |dx: isize, dy: isize| {
+            // Huge coordinates saturate at the largest index
+            let x = self.wrap.apply((x0 as isize).saturating_add(dx), *width);
+            let y = self.wrap.apply((y0 as isize).saturating_add(dy), *height);
+            data[y * width + x]
+        };
+        let top = pixel(0, 0) * (1. - tx) + pixel(1, 0) * tx;
+        let bottom = pixel(0, 1) * (1. - tx) + pixel(1, 1) * tx;
+        top * (1. - ty) + bottom * ty
+    }
+}
+
+/// Gradient noise in about -1..1, with a pseudo-random gradient at every
+/// integer point
+fn perlin(p: Vec3) -> f32 {
+    let cell = p.map(f32::floor);
+    let offset = p - cell;
+    // Smooth the interpolation so that there are no visible creases
+    let fade = offset.map(|t| t * t * t * (t * (t * 6. - 15.) + 10.));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let corner = Vec3::new(dx as f32, dy as f32, dz as f32);
+        let hash = hash(cell.x as i32 + dx, cell.y as i32 + dy, cell.z as i32 + dz);
+        gradient(hash, offset - corner)
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fade.x);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fade.x);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fade.x);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fade.x);
+    lerp(lerp(x00, x10, fade.y), lerp(x01, x11, fade.y), fade.z)
+}
+
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+/// The dot product with one of the 12 gradients pointing to the edges of a
+/// cube, like in Ken Perlin's improved noise
+fn gradient(hash: u32, d: Vec3) -> f32 {
+    match hash % 12 {
+        0 => d.x + d.y,
+        1 => -d.x + d.y,
+        2 => d.x - d.y,
+        3 => -d.x - d.y,
+        4 => d.x + d.z,
+        5 => -d.x + d.z,
+        6 => d.x - d.z,
+        7 => -d.x - d.z,
+        8 => d.y + d.z,
+        9 => -d.y + d.z,
+        10 => d.y - d.z,
+        _ => -d.y - d.z,
+    }
+}
+
+/// Noise with every octave twice as detailed and half as strong as the last
+fn fractal_noise(p: Vec3, octaves: usize) -> f32 {
+    (0..octaves)
+        .map(|i| perlin(p * 2f32.powi(i as i32)) / 2f32.powi(i as i32))
+        .sum()
+}
+
+/// Like `fractal_noise`, but made of ridges where the noise crosses zero
+fn fractal_turbulence(p: Vec3, octaves: usize) -> f32 {
+    (0..octaves)
+        .map(|i| perlin(p * 2f32.powi(i as i32)).abs() / 2f32.powi(i as i32))
+        .sum()
+}
+
+pub(crate) fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let image = Image::new(vec![Vec3::zero(), Vec3::one()], 2, 1);
+        let texture = ImageTexture::new(image);
+        // Halfway between the pixel centers
+        assert_eq!(texture.sample(Vec2::new(0.5, 0.5)), Vec3::broadcast(0.5));
+        assert_eq!(texture.sample(Vec2::new(0.25, 0.5)), Vec3::zero());
+        // Repeating blends the last pixel with the first one
+        assert_eq!(texture.sample(Vec2::new(1., 0.5)), Vec3::broadcast(0.5));
+
+        let texture = texture.with_wrap(WrapMode::Clamp);
+        assert_eq!(texture.sample(Vec2::new(1., 0.5)), Vec3::one());
+        let texture = texture.with_wrap(WrapMode::Mirror);
+        assert_eq!(texture.sample(Vec2::new(1.25, 0.5)), Vec3::one());
+
+        // Out of range coordinates don't panic
+        for wrap in [WrapMode::Repeat, WrapMode::Mirror, WrapMode::Clamp] {
+            let texture = texture.clone().with_wrap(wrap);
+            assert_eq!(texture.sample(Vec2::broadcast(f32::INFINITY)), Vec3::one());
+            assert_eq!(texture.sample(Vec2::broadcast(f32::NAN)), Vec3::one());
+            texture.sample(Vec2::broadcast(1e30));
+        }
+    }
+
+    #[test]
+    fn test_noise() {
+        for i in 0..1000 {
+            let p = Vec3::new(i as f32 * 0.37, i as f32 * 0.11, i as f32 * -0.23);
+            let noise = perlin(p);
+            assert!((-1. ..=1.).contains(&noise), "p={} noise={}", p, noise);
+            // The noise is zero at integer points and continuous
+            assert_eq!(perlin(p.map(f32::floor)), 0.);
+            assert!((perlin(p + Vec3::broadcast(1e-3)) - noise).abs() < 0.01);
+        }
+    }
+}
@@ -1,28 +1,33 @@
-use im_already_raytracer::camera::MappingFunction;
-use im_already_raytracer::render::RenderOptions;
-use im_already_raytracer::shapes::Shape;
-use im_already_raytracer::{presets, render, Camera, Light, Quaternion, Vec3};
+use im_already_raytracer::camera::{Camera, MappingFunction};
+use im_already_raytracer::filter::Filter;
+use im_already_raytracer::image::ToneMapping;
+use im_already_raytracer::render::{Integrator, RenderOptions};
+use im_already_raytracer::{
+    presets, DisplayTransform, ProgressiveRenderer, Quaternion, Scene, Vec3,
+};
 
 use bevy::input::mouse::{MouseMotion, MouseWheel};
 use bevy::prelude::*;
 use bevy_pixels::prelude::*;
 
 fn main() {
-    let (camera, shapes, lights) = presets::cornellbox();
+    let mut scene = presets::cornellbox();
+    // The geometry is static, so the hierarchy only has to be built once
+    scene.build_bvh();
     App::new()
         .add_plugins(DefaultPlugins)
         .add_plugin(PixelsPlugin)
-        .insert_resource(camera)
-        .insert_resource(shapes)
-        .insert_resource(lights)
+        .insert_resource(scene)
         .insert_resource(RenderOptions {
             width: 128,
             height: 72,
-            multisampling: 1,
-            soft_shadow_resolution: 1,
+            pixel_samples: 1,
+            shadow_samples: 1,
             max_ray_depth: 3,
-            use_randomness: false,
+            // Noise averages out over the passes while the camera is still
+            use_randomness: true,
             clamp_colors: true,
+            ..Default::default()
         })
         .add_system(render_s.system())
         .add_system(input_s.system())
@@ -31,10 +36,10 @@ fn main() {
 
 fn render_s(
     mut pixels: ResMut<PixelsResource>,
-    camera: Res<Camera>,
-    shapes: Res<Vec<Shape>>,
-    lights: Res<Vec<Light>>,
+    scene: Res<Scene>,
     render_options: Res<RenderOptions>,
+    mut renderer: Local<ProgressiveRenderer>,
+    mut last_frame: Local<Option<(Camera, RenderOptions)>>,
 ) {
     let pw = pixels.pixels.context().texture_extent.width as u32;
     let ph = pixels.pixels.context().texture_extent.height as u32;
@@ -43,10 +48,25 @@ fn render_s(
             .pixels
             .resize_buffer(render_options.width as u32, render_options.height as u32);
     }
+    // Keep refining the image until anything that affects it changes
+    // The display transform is applied afterwards, so it can change freely
+    let current = (
+        scene.camera.clone(),
+        RenderOptions {
+            display: DisplayTransform::new(),
+            ..render_options.clone()
+        },
+    );
+    if last_frame.as_ref() != Some(&current) {
+        renderer.reset();
+        *last_frame = Some(current);
+    }
+    renderer.pass(&render_options, &scene);
     let frame: &mut [u8] = pixels.pixels.get_frame();
     frame.copy_from_slice(
-        render(&render_options, &camera, &shapes, &lights)
-            .get_raw_data()
+        renderer
+            .image()
+            .get_raw_data(&render_options.display)
             .as_ref(),
     );
 }
@@ -56,7 +76,7 @@ fn input_s(
     mouse: Res<Input<MouseButton>>,
     mut cursor: EventReader<MouseMotion>,
     mut scroll: EventReader<MouseWheel>,
-    mut camera: ResMut<Camera>,
+    mut scene: ResMut<Scene>,
     mut yaw: Local<f32>,
     mut pitch: Local<f32>,
     mut windows: ResMut<Windows>,
@@ -66,9 +86,10 @@ fn input_s(
 ) {
     use std::f32::consts::FRAC_PI_2;
 
-    if camera.is_added() {
-        *lfov = (camera.fov + FRAC_PI_2).tan();
+    if scene.is_added() {
+        *lfov = (scene.camera.fov + FRAC_PI_2).tan();
     }
+    let camera = &mut scene.camera;
     let locked = windows.get_primary().unwrap().cursor_locked();
     if keyboard.just_pressed(KeyCode::Escape) || mouse.just_pressed(MouseButton::Left) {
         windows
@@ -137,17 +158,37 @@ fn input_s(
     if keyboard.pressed(KeyCode::Key3) {
         camera.mapping_function = MappingFunction::Circular;
     }
-    if keyboard.just_pressed(KeyCode::Minus) && render_options.multisampling > 1 {
-        render_options.multisampling -= 1;
+    if keyboard.just_pressed(KeyCode::Z) {
+        camera.aperture = (camera.aperture - 0.05).max(0.);
+    }
+    if keyboard.just_pressed(KeyCode::X) {
+        camera.aperture += 0.05;
+    }
+    if keyboard.pressed(KeyCode::C) {
+        camera.focus_distance = (camera.focus_distance - move_speed).max(0.1);
+    }
+    if keyboard.pressed(KeyCode::V) {
+        camera.focus_distance += move_speed;
+    }
+    if keyboard.just_pressed(KeyCode::Minus) && render_options.pixel_samples > 1 {
+        render_options.pixel_samples -= 1;
     }
     if keyboard.just_pressed(KeyCode::Equals) {
-        render_options.multisampling += 1;
+        render_options.pixel_samples += 1;
     }
-    if keyboard.just_pressed(KeyCode::LBracket) && render_options.soft_shadow_resolution > 0 {
-        render_options.soft_shadow_resolution -= 1;
+    if keyboard.just_pressed(KeyCode::F) {
+        render_options.filter = match render_options.filter {
+            Filter::Box => Filter::Tent,
+            Filter::Tent => Filter::Gaussian,
+            Filter::Gaussian => Filter::Mitchell,
+            Filter::Mitchell => Filter::Box,
+        };
+    }
+    if keyboard.just_pressed(KeyCode::LBracket) && render_options.shadow_samples > 1 {
+        render_options.shadow_samples -= 1;
     }
     if keyboard.just_pressed(KeyCode::RBracket) {
-        render_options.soft_shadow_resolution += 1;
+        render_options.shadow_samples += 1;
     }
     if keyboard.just_pressed(KeyCode::Semicolon) && render_options.max_ray_depth > 0 {
         render_options.max_ray_depth -= 1;
@@ -158,4 +199,23 @@ fn input_s(
     if keyboard.just_pressed(KeyCode::R) {
         render_options.use_randomness = !render_options.use_randomness;
     }
+    if keyboard.just_pressed(KeyCode::Comma) {
+        render_options.display.exposure -= 0.5;
+    }
+    if keyboard.just_pressed(KeyCode::Period) {
+        render_options.display.exposure += 0.5;
+    }
+    if keyboard.just_pressed(KeyCode::T) {
+        render_options.display.tone_mapping = match render_options.display.tone_mapping {
+            ToneMapping::Clamp => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::AcesFilmic,
+            _ => ToneMapping::Clamp,
+        };
+    }
+    if keyboard.just_pressed(KeyCode::P) {
+        render_options.integrator = match render_options.integrator {
+            Integrator::Whitted => Integrator::PathTracing,
+            Integrator::PathTracing => Integrator::Whitted,
+        };
+    }
 }
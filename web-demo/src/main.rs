@@ -15,11 +15,18 @@ static mut OPTIONS: im_already_raytracer::render::RenderOptions =
     im_already_raytracer::render::RenderOptions {
         width: 40,
         height: 30,
-        multisampling: 1,
-        soft_shadow_resolution: 2,
+        pixel_samples: 1,
+        filter: im_already_raytracer::filter::Filter::Box,
+        adaptive: None,
+        shadow_samples: 4,
         max_ray_depth: 3,
         use_randomness: true,
         clamp_colors: true,
+        threads: 1,
+        seed: None,
+        integrator: im_already_raytracer::render::Integrator::Whitted,
+        environment_samples: 2,
+        display: im_already_raytracer::DisplayTransform::new(),
     };
 
 static mut BUTTONS: Buttons = Buttons {
@@ -85,41 +92,38 @@ pub fn set_depth(d: usize) {
 }
 
 struct World {
-    camera: im_already_raytracer::Camera,
-    shapes: Vec<im_already_raytracer::Shape>,
-    lights: Vec<im_already_raytracer::Light>,
+    scene: im_already_raytracer::Scene,
     yaw: f32,
     pitch: f32,
 }
 
 impl World {
     fn new() -> Self {
-        let (camera, shapes, lights) = im_already_raytracer::presets::cornellbox();
+        let mut scene = im_already_raytracer::presets::cornellbox();
+        scene.build_bvh();
         Self {
-            camera,
-            shapes,
-            lights,
+            scene,
             yaw: 0.,
             pitch: 0.,
         }
     }
 
     fn update(&mut self, dt: f32) {
-        let local_forwards = self.camera.orientation * Vec3::unit_z();
-        let local_right = self.camera.orientation * Vec3::unit_x();
+        let local_forwards = self.scene.camera.orientation * Vec3::unit_z();
+        let local_right = self.scene.camera.orientation * Vec3::unit_x();
         let movement = dt * 0.008;
         let btns = unsafe { &BUTTONS };
         if btns.w {
-            self.camera.position -= local_forwards * movement;
+            self.scene.camera.position -= local_forwards * movement;
         }
         if btns.a {
-            self.camera.position -= local_right * movement;
+            self.scene.camera.position -= local_right * movement;
         }
         if btns.s {
-            self.camera.position += local_forwards * movement;
+            self.scene.camera.position += local_forwards * movement;
         }
         if btns.d {
-            self.camera.position += local_right * movement;
+            self.scene.camera.position += local_right * movement;
         }
 
         let spinnyspin = dt * 0.001;
@@ -135,7 +139,7 @@ impl World {
         if btns.n {
             self.pitch -= spinnyspin;
         }
-        self.camera.orientation =
+        self.scene.camera.orientation =
             Quaternion::rotation_y(self.yaw) * Quaternion::rotation_x(self.pitch);
     }
 
@@ -147,9 +151,10 @@ impl World {
             pixels.set_clear_color(wgpu::Color::TRANSPARENT);
             pixels.resize_buffer(options.width as u32, options.height as u32);
         }
-        let image =
-            im_already_raytracer::render(&options, &self.camera, &self.shapes, &self.lights);
-        pixels.get_frame().copy_from_slice(&image.get_raw_data());
+        let image = im_already_raytracer::render(&options, &self.scene);
+        pixels
+            .get_frame()
+            .copy_from_slice(&image.get_raw_data(&options.display));
     }
 }
 